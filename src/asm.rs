@@ -0,0 +1,614 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::disasm::ROM_START;
+
+// Guards against files including each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Assembly failure, pointing at the offending token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles Octo-style source into a ROM, resolving `:include` paths
+/// against the working directory.
+///
+/// The syntax is the one `disasm::Listing` prints: `: name` defines a label,
+/// `:const name value` a constant, `:byte` emits the values up to the end of
+/// its line and `#` starts a comment. Numbers are decimal, `0x` hex or `0b`
+/// binary.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens = tokenize(source, None, Path::new("."), 0)?;
+    Assembler::new(tokens).run()
+}
+
+/// Assembles the file at `path`, resolving `:include` paths against the
+/// directory it lives in.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let tokens = include(path, None, 0)?;
+    Assembler::new(tokens).run()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    file: Option<Rc<str>>,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.as_ref().map(|file| file.to_string()),
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+fn include(path: &Path, from: Option<&Token>, depth: usize) -> Result<Vec<Token>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|err| {
+        let message = format!("unable to read {}: {}", name, err);
+        match from {
+            Some(token) => token.error(message),
+            None => AsmError {
+                file: None,
+                line: 0,
+                column: 0,
+                message,
+            },
+        }
+    })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    tokenize(&source, Some(Rc::from(name)), dir, depth)
+}
+
+/// Splits `source` into whitespace separated tokens and splices in the
+/// contents of `:include` directives.
+fn tokenize(
+    source: &str,
+    file: Option<Rc<str>>,
+    dir: &Path,
+    depth: usize,
+) -> Result<Vec<Token>, AsmError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut pos = 0;
+        while pos < chars.len() {
+            if chars[pos].is_whitespace() {
+                pos += 1;
+                continue;
+            }
+            if chars[pos] == '#' {
+                break;
+            }
+
+            let start = pos;
+            if chars[pos] == '"' {
+                pos += 1;
+                while pos < chars.len() && chars[pos] != '"' {
+                    pos += 1;
+                }
+                pos = (pos + 1).min(chars.len());
+            } else {
+                while pos < chars.len() && !chars[pos].is_whitespace() {
+                    pos += 1;
+                }
+            }
+            tokens.push(Token {
+                text: chars[start..pos].iter().collect(),
+                file: file.clone(),
+                line: line_idx + 1,
+                column: start + 1,
+            });
+        }
+    }
+
+    let mut spliced = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter();
+    while let Some(token) = iter.next() {
+        if token.text != ":include" {
+            spliced.push(token);
+            continue;
+        }
+        let name = match iter.next() {
+            Some(name) => name,
+            None => return Err(token.error(":include needs a file name".to_string())),
+        };
+        let path = match name
+            .text
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+        {
+            Some(path) => PathBuf::from(path),
+            None => {
+                return Err(name.error(format!(
+                    "expected a quoted file name, found `{}`",
+                    name.text
+                )))
+            }
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(name.error("includes are nested too deeply".to_string()));
+        }
+        spliced.extend(include(&dir.join(path), Some(&name), depth + 1)?);
+    }
+    Ok(spliced)
+}
+
+/// How the value of an operand is range checked and merged into its opcode.
+#[derive(Clone, Copy)]
+enum Field {
+    Nibble,
+    /// XO-CHIP plane mask, which sits in the X nibble
+    Plane,
+    Byte,
+    Addr,
+}
+
+enum Item {
+    Bytes(Vec<u8>),
+    /// `op` with the value of `operand` or'ed into `field`
+    Op {
+        op: u16,
+        operand: Option<(Field, Token)>,
+    },
+    /// `i := long`, whose operand is a whole word
+    Long(Token),
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    addr: usize,
+    symbols: HashMap<String, i64>,
+    items: Vec<Item>,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Assembler {
+            tokens,
+            pos: 0,
+            addr: ROM_START as usize,
+            symbols: HashMap::new(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Parses every statement, then fills in operands once all labels are
+    /// known.
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        let mut rom = Vec::new();
+        for item in &self.items {
+            match item {
+                Item::Bytes(bytes) => rom.extend_from_slice(bytes),
+                Item::Op { op, operand } => {
+                    let op = match operand {
+                        None => *op,
+                        Some((field, token)) => *op | self.field(*field, token)?,
+                    };
+                    rom.extend_from_slice(&op.to_be_bytes());
+                }
+                Item::Long(token) => {
+                    let value = self.value(token)?;
+                    if !(0..=0xFFFF).contains(&value) {
+                        return Err(token.error(format!("{} does not fit in 16 bits", value)));
+                    }
+                    rom.extend_from_slice(&[0xF0, 0x00]);
+                    rom.extend_from_slice(&(value as u16).to_be_bytes());
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name, self.addr as i64)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.define(&name, value)?;
+            }
+            ":byte" => {
+                let mut bytes = Vec::new();
+                while let Some(value) = self.tokens.get(self.pos) {
+                    if value.line != token.line || value.file != token.file {
+                        break;
+                    }
+                    let value = value.clone();
+                    self.pos += 1;
+                    bytes.push(self.field(Field::Byte, &value)? as u8);
+                }
+                if bytes.is_empty() {
+                    return Err(token.error(":byte needs at least one value".to_string()));
+                }
+                self.addr += bytes.len();
+                self.items.push(Item::Bytes(bytes));
+            }
+            "clear" => self.emit(0x00E0, None),
+            "return" => self.emit(0x00EE, None),
+            "scroll-right" => self.emit(0x00FB, None),
+            "scroll-left" => self.emit(0x00FC, None),
+            "exit" => self.emit(0x00FD, None),
+            "lores" => self.emit(0x00FE, None),
+            "hires" => self.emit(0x00FF, None),
+            "audio" => self.emit(0xF002, None),
+            "scroll-down" => {
+                let n = self.next()?;
+                self.emit(0x00C0, Some((Field::Nibble, n)));
+            }
+            "scroll-up" => {
+                let n = self.next()?;
+                self.emit(0x00D0, Some((Field::Nibble, n)));
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit(0x1000, Some((Field::Addr, target)));
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit(0x2000, Some((Field::Addr, target)));
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit(0xB000, Some((Field::Addr, target)));
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.next()?;
+                self.emit(0xD000 | x << 8 | y << 4, Some((Field::Nibble, n)));
+            }
+            "plane" => {
+                let mask = self.next()?;
+                self.emit(0xF001, Some((Field::Plane, mask)));
+            }
+            "bcd" => self.register_op(0xF033)?,
+            "saveflags" => self.register_op(0xF075)?,
+            "loadflags" => self.register_op(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = self.register()?;
+                    let op = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | x << 8 | y << 4, None);
+                } else {
+                    let op = if token.text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | x << 8, None);
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let op = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_op(op)?;
+            }
+            "i" => self.index()?,
+            "if" => self.condition()?,
+            _ => match parse_register(&token.text) {
+                Some(x) => self.assignment(x)?,
+                None => return Err(token.error(format!("unknown instruction `{}`", token.text))),
+            },
+        }
+        Ok(())
+    }
+
+    /// `i := ...` and `i += vX`
+    fn index(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                let operand = self.next()?;
+                match operand.text.as_str() {
+                    "long" => {
+                        let value = self.next()?;
+                        self.addr += 4;
+                        self.items.push(Item::Long(value));
+                    }
+                    "hex" => self.register_op(0xF029)?,
+                    "bighex" => self.register_op(0xF030)?,
+                    _ => self.emit(0xA000, Some((Field::Addr, operand))),
+                }
+            }
+            "+=" => self.register_op(0xF01E)?,
+            _ => return Err(op.error(format!("expected `:=` or `+=`, found `{}`", op.text))),
+        }
+        Ok(())
+    }
+
+    /// `if vX <cond> then`, encoded as the skip that jumps over the next
+    /// instruction when the condition does not hold.
+    fn condition(&mut self) -> Result<(), AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => self.emit(0xE0A1 | x << 8, None),
+            "-key" => self.emit(0xE09E | x << 8, None),
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                let equal = op.text == "==";
+                match parse_register(&rhs.text) {
+                    Some(y) => {
+                        let base = if equal { 0x9000 } else { 0x5000 };
+                        self.emit(base | x << 8 | y << 4, None);
+                    }
+                    None => {
+                        let base = if equal { 0x4000 } else { 0x3000 };
+                        self.emit(base | x << 8, Some((Field::Byte, rhs)));
+                    }
+                }
+            }
+            _ => return Err(op.error(format!("unknown condition `{}`", op.text))),
+        }
+        self.expect("then")
+    }
+
+    /// Everything of the form `vX <op> ...`.
+    fn assignment(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let alu = match op.text.as_str() {
+            ":=" => Some(0),
+            "|=" => Some(1),
+            "&=" => Some(2),
+            "^=" => Some(3),
+            "+=" => Some(4),
+            "-=" => Some(5),
+            ">>=" => Some(6),
+            "=-" => Some(7),
+            "<<=" => Some(0xE),
+            _ => None,
+        };
+        let alu = match alu {
+            Some(alu) => alu,
+            None => return Err(op.error(format!("unknown operator `{}`", op.text))),
+        };
+
+        let rhs = self.next()?;
+        if let Some(y) = parse_register(&rhs.text) {
+            self.emit(0x8000 | x << 8 | y << 4 | alu, None);
+            return Ok(());
+        }
+        match (op.text.as_str(), rhs.text.as_str()) {
+            (":=", "random") => {
+                let mask = self.next()?;
+                self.emit(0xC000 | x << 8, Some((Field::Byte, mask)));
+            }
+            (":=", "delay") => self.emit(0xF007 | x << 8, None),
+            (":=", "key") => self.emit(0xF00A | x << 8, None),
+            (":=", _) => self.emit(0x6000 | x << 8, Some((Field::Byte, rhs))),
+            ("+=", _) => self.emit(0x7000 | x << 8, Some((Field::Byte, rhs))),
+            _ => return Err(rhs.error(format!("expected a register, found `{}`", rhs.text))),
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, op: u16, operand: Option<(Field, Token)>) {
+        self.addr += 2;
+        self.items.push(Item::Op { op, operand });
+    }
+
+    /// Emits `op` with a register in the X nibble.
+    fn register_op(&mut self, op: u16) -> Result<(), AsmError> {
+        let x = self.register()?;
+        self.emit(op | x << 8, None);
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token, value: i64) -> Result<(), AsmError> {
+        let valid = name
+            .text
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && parse_register(&name.text).is_none();
+        if !valid {
+            return Err(name.error(format!("`{}` is not a valid name", name.text)));
+        }
+        if self.symbols.insert(name.text.clone(), value).is_some() {
+            return Err(name.error(format!("`{}` is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn value(&self, token: &Token) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        match self.symbols.get(&token.text) {
+            Some(&value) => Ok(value),
+            None => Err(token.error(format!("undefined name `{}`", token.text))),
+        }
+    }
+
+    fn field(&self, field: Field, token: &Token) -> Result<u16, AsmError> {
+        let value = self.value(token)?;
+        let (range, what) = match field {
+            Field::Nibble | Field::Plane => (0..=0xF, "4 bits"),
+            // Negative bytes wrap around, as with `v0 += -1`
+            Field::Byte => (-0x80..=0xFF, "a byte"),
+            Field::Addr => (0..=0xFFF, "12 bits"),
+        };
+        if !range.contains(&value) {
+            return Err(token.error(format!("{} does not fit in {}", value, what)));
+        }
+        Ok(match field {
+            Field::Plane => (value as u16) << 8,
+            Field::Byte => value as u16 & 0xFF,
+            _ => value as u16,
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let last = self.tokens.last().unwrap();
+                Err(AsmError {
+                    file: last.file.as_ref().map(|file| file.to_string()),
+                    line: last.line,
+                    column: last.column + last.text.chars().count(),
+                    message: "unexpected end of input".to_string(),
+                })
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected `{}`, found `{}`", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        parse_register(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register, found `{}`", token.text)))
+    }
+}
+
+/// `v0` to `vF`, in either case.
+fn parse_register(text: &str) -> Option<u16> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u16::from_str_radix(digit, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError};
+    use crate::disasm::Listing;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            :const SPEED 3
+            : main
+                v0 := SPEED     # comment
+                v1 += -1
+                i := sprite
+                if v0 != v1 then jump main
+                sprite v0 v1 2
+                :call sub
+                i := long 0x1234
+            : sub
+                save v1 - v2
+                return
+            : sprite
+                :byte 0xF0 0b10010000
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [
+                0x60, 0x03, 0x71, 0xFF, 0xA2, 0x16, 0x50, 0x10, 0x12, 0x00, 0xD0, 0x12, 0x22, 0x12,
+                0xF0, 0x00, 0x12, 0x34, 0x51, 0x22, 0x00, 0xEE, 0xF0, 0x90,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("clear\n  v0 := 0x100"),
+            AsmError {
+                file: None,
+                line: 2,
+                column: 9,
+                message: "256 does not fit in a byte".to_string()
+            }
+        );
+        assert_eq!(error("jump nowhere").message, "undefined name `nowhere`");
+        assert_eq!(error(": a\n: a").to_string(), "2:3: `a` is already defined");
+        assert_eq!(
+            error("sprite v0 1 2").message,
+            "expected a register, found `1`"
+        );
+        assert_eq!(error("if v0 == 1").message, "unexpected end of input");
+        assert_eq!(error("bogus").message, "unknown instruction `bogus`");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for rom in [
+            &include_bytes!("../roms/TETRIS.ch8")[..],
+            &include_bytes!("../roms/SPACE_INVADERS.ch8")[..],
+            &include_bytes!("../roms/Landing.ch8")[..],
+            // Every XO-CHIP and SUPER-CHIP addition
+            &[
+                0x00, 0xC4, 0x00, 0xD2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFE, 0x00, 0xFF, 0x51, 0x22,
+                0x51, 0x23, 0xF0, 0x00, 0xFF, 0xFF, 0xF2, 0x01, 0xF0, 0x02, 0xF1, 0x30, 0xF3, 0x3A,
+                0xF1, 0x75, 0xF1, 0x85, 0x00, 0xFD,
+            ][..],
+        ] {
+            let source = Listing::new(rom).to_string();
+            assert_eq!(assemble(&source).unwrap(), rom, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::asm::assemble_file;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("Usage: {} path/to/source.8o [path/to/game.ch8]", &args[0]);
+        std::process::exit(2);
+    }
+
+    let source = Path::new(&args[1]);
+    let output = match args.get(2) {
+        Some(output) => PathBuf::from(output),
+        None => source.with_extension("ch8"),
+    };
+
+    let rom = match assemble_file(source) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = fs::write(&output, &rom) {
+        eprintln!("{}: {}", output.display(), err);
+        std::process::exit(1);
+    }
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+}
@@ -0,0 +1,23 @@
+use std::env;
+use std::fs;
+
+use chip8::disasm::Listing;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} path/to/game", &args[0]);
+        std::process::exit(2);
+    }
+
+    let rom = match fs::read(&args[1]) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}: {}", &args[1], err);
+            std::process::exit(1);
+        }
+    };
+
+    println!("# {} ({} bytes)", &args[1], rom.len());
+    print!("{}", Listing::new(&rom));
+}
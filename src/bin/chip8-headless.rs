@@ -0,0 +1,255 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use chip8::chip8::Chip8;
+use chip8::debugger::print_registers;
+use chip8::headless::{screen_hash, to_ascii, to_pbm, to_png, Headless, KeyEvent, Limit};
+use chip8::movie::Movie;
+use chip8::romdb::{rom_settings, RomDb};
+use chip8::scheduler::Scheduler;
+
+const USAGE: &str = "Usage: chip8-headless [options] path/to/game
+
+Options:
+  --frames N          run for N frames at 60Hz (default 600)
+  --cycles N          run for N instructions instead
+  --ipf N             instructions per frame (default from the ROM database
+                      or the platform)
+  --seed N            seed for the random generator (default 0)
+  --vip-timing        charge instructions their COSMAC VIP cycles, with a
+                      1.76MHz budget per frame instead of a fixed IPF
+  --press KEY@FRAME   press a key (hex) at the start of a frame
+  --release KEY@FRAME release a key at the start of a frame
+  --png FILE          write the final screen as PNG
+  --pbm FILE          write the final screen as PBM
+  --scale N           enlarge PNG and PBM output N times, 1 to 64 (default 1)
+  --ascii             print the final screen as text
+  --regs              print registers, timers and stack
+  --expect-hash HASH  fail unless the final frame hash matches
+  --movie FILE        replay a movie recorded with `chip8 --record`, checking
+                      its final frame hash; replaces the run, key and seed
+                      options
+
+Exits with 1 when the program faults or the hash does not match.";
+
+struct Options {
+    rom: String,
+    limit: Limit,
+    ipf: Option<usize>,
+    seed: u64,
+    vip_timing: bool,
+    events: Vec<KeyEvent>,
+    png: Option<String>,
+    pbm: Option<String>,
+    scale: usize,
+    ascii: bool,
+    regs: bool,
+    expect_hash: Option<u64>,
+    movie: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        limit: Limit::Frames(600),
+        ipf: None,
+        seed: 0,
+        vip_timing: false,
+        events: Vec::new(),
+        png: None,
+        pbm: None,
+        scale: 1,
+        ascii: false,
+        regs: false,
+        expect_hash: None,
+        movie: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if !options.rom.is_empty() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            options.rom = arg.clone();
+            continue;
+        }
+        if arg == "--ascii" {
+            options.ascii = true;
+            continue;
+        }
+        if arg == "--regs" {
+            options.regs = true;
+            continue;
+        }
+        if arg == "--vip-timing" {
+            options.vip_timing = true;
+            continue;
+        }
+
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{}: invalid number {}", arg, value))
+        };
+        match arg.as_str() {
+            "--frames" => options.limit = Limit::Frames(number()?),
+            "--cycles" => options.limit = Limit::Cycles(number()?),
+            "--ipf" => match number()? {
+                0 => return Err(format!("{}: must be at least 1", arg)),
+                ipf => options.ipf = Some(ipf as usize),
+            },
+            "--seed" => options.seed = number()?,
+            "--scale" => {
+                options.scale = match number()? {
+                    scale @ 1..=64 => scale as usize,
+                    scale => return Err(format!("{}: expected 1 to 64, got {}", arg, scale)),
+                }
+            }
+            "--press" | "--release" => {
+                let event = KeyEvent::parse(value, arg == "--press")
+                    .ok_or_else(|| format!("{}: expected KEY@FRAME, got {}", arg, value))?;
+                options.events.push(event);
+            }
+            "--png" => options.png = Some(value.clone()),
+            "--pbm" => options.pbm = Some(value.clone()),
+            "--movie" => options.movie = Some(value.clone()),
+            "--expect-hash" => {
+                let hash = u64::from_str_radix(value.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("{}: invalid hash {}", arg, value))?;
+                options.expect_hash = Some(hash);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.rom.is_empty() {
+        return Err("no ROM given".to_string());
+    }
+    Ok(options)
+}
+
+fn write_image(path: &str, data: &[u8]) -> bool {
+    match fs::write(path, data) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            false
+        }
+    }
+}
+
+/// Runs the ROM as configured on the command line.
+fn rom_run(options: &Options, rom: &[u8]) -> Result<(Headless, Limit, Option<u64>), String> {
+    let romdb = RomDb::load()?;
+    let info = romdb.lookup(rom);
+    let (platform, quirks, ipf) = rom_settings(info, None, options.ipf, Path::new(&options.rom));
+    let mut chip8 = Chip8::new(quirks)
+        .with_platform(platform)
+        .with_seed(options.seed)
+        .with_vip_timing(options.vip_timing);
+    chip8
+        .load(rom)
+        .map_err(|err| format!("{}: {}", options.rom, err))?;
+
+    let ipf = Scheduler::for_chip8(&chip8, ipf).ipf();
+    let headless = Headless::new(chip8, ipf)
+        .and_then(|headless| headless.with_events(options.events.clone()))
+        .map_err(|err| err.to_string())?;
+    Ok((headless, options.limit, None))
+}
+
+/// Replays a movie, expecting the frame hash it was recorded with.
+fn movie_run(path: &str, rom: &[u8]) -> Result<(Headless, Limit, Option<u64>), String> {
+    let load = || -> Result<(Movie, Headless), Box<dyn Error>> {
+        let movie = Movie::from_bytes(&fs::read(path)?)?;
+        let headless = movie.playback(rom)?;
+        Ok((movie, headless))
+    };
+    let (movie, headless) = load().map_err(|err| format!("{}: {}", path, err))?;
+    Ok((
+        headless,
+        Limit::Frames(movie.len() as u64),
+        movie.final_hash,
+    ))
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}: {}", options.rom, err);
+            process::exit(1);
+        }
+    };
+
+    let setup = match &options.movie {
+        Some(path) => movie_run(path, &rom),
+        None => rom_run(&options, &rom),
+    };
+    let (mut headless, limit, recorded_hash) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let expect_hash = options.expect_hash.or(recorded_hash);
+    let result = headless.run(limit);
+
+    let chip8 = headless.chip8();
+    let screen = chip8.get_screen();
+    let (width, height) = chip8.get_resolution();
+    let hash = screen_hash(chip8);
+
+    if options.ascii {
+        print!("{}", to_ascii(screen, width));
+    }
+    if options.regs {
+        print_registers(chip8, &mut io::stdout()).unwrap();
+    }
+    println!(
+        "frames: {} cycles: {} hash: {:016x}",
+        headless.frame(),
+        headless.cycles(),
+        hash
+    );
+
+    let mut failed = false;
+    if let Some(path) = &options.png {
+        failed |= !write_image(path, &to_png(screen, width, height, options.scale));
+    }
+    if let Some(path) = &options.pbm {
+        failed |= !write_image(path, &to_pbm(screen, width, height, options.scale));
+    }
+
+    if let Err(err) = result {
+        eprintln!("Emulation halted: {}", err);
+        failed = true;
+    }
+    if let Some(expected) = expect_hash {
+        if expected != hash {
+            eprintln!("Frame hash {:016x} does not match {:016x}", hash, expected);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,278 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use chip8::chip8::Chip8;
+use chip8::keymap::Keymap;
+use chip8::machine::Machine;
+use chip8::romdb::{rom_settings, RomDb};
+use chip8::scheduler::Scheduler;
+use chip8::tui::{compose, Glyphs, TerminalBeeper, TerminalDisplay, TerminalKeypad};
+
+const USAGE: &str = "Usage: chip8-tui [options] path/to/game
+
+Plays a game in the terminal. Keys are typed characters, 1234 QWER ASDF
+ZXCV on a QWERTY keyboard; Esc or Ctrl+C quits.
+
+Options:
+  --glyphs half|braille draw 1x2 pixels per character with half blocks
+                        (default), or 2x4 with Braille dots
+  --keymap PRESET       keyboard layout: qwerty, qwertz, azerty or dvorak
+  --ipf N               instructions per frame (default from the ROM database
+                        or the platform)
+  --seed N              seed for the random generator
+  --vip-timing          charge instructions their COSMAC VIP cycles
+  --mute                don't ring the terminal bell for the sound timer
+  --help                show this help";
+
+struct Options {
+    rom: String,
+    glyphs: Glyphs,
+    keymap: Keymap,
+    ipf: Option<usize>,
+    seed: Option<u64>,
+    vip_timing: bool,
+    mute: bool,
+    help: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        glyphs: Glyphs::default(),
+        keymap: Keymap::preset("qwerty").unwrap(),
+        ipf: None,
+        seed: None,
+        vip_timing: false,
+        mute: false,
+        help: false,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if !options.rom.is_empty() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            options.rom = arg.clone();
+            continue;
+        }
+        match arg.as_str() {
+            "--help" => {
+                options.help = true;
+                return Ok(options);
+            }
+            "--vip-timing" => {
+                options.vip_timing = true;
+                continue;
+            }
+            "--mute" => {
+                options.mute = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{}: invalid number {}", arg, value))
+        };
+        match arg.as_str() {
+            "--glyphs" => {
+                options.glyphs = Glyphs::from_name(value)
+                    .ok_or_else(|| format!("{}: expected half or braille, got {}", arg, value))?
+            }
+            "--keymap" => {
+                options.keymap = Keymap::preset(value).ok_or_else(|| {
+                    format!(
+                        "{}: unknown preset {}, expected one of {}",
+                        arg,
+                        value,
+                        Keymap::PRESETS.join(", ")
+                    )
+                })?
+            }
+            "--ipf" => match number()? {
+                0 => return Err(format!("{}: needs at least 1", arg)),
+                ipf => options.ipf = Some(ipf as usize),
+            },
+            "--seed" => options.seed = Some(number()?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.rom.is_empty() {
+        return Err("no ROM given".to_string());
+    }
+    Ok(options)
+}
+
+/// The terminal in raw mode, without echo, on the alternate screen. It is
+/// put back the way it was when dropped.
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<Self, String> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo", "min", "0", "time", "0"])?;
+        // Alternate screen, hidden cursor, cleared
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+        Ok(RawTerminal {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Runs `stty` on the terminal behind stdin, returning what it printed.
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("stty: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "stty: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Hands typed characters to the keypad, returning false once the player
+/// asked to quit.
+fn read_keys(keypad: &mut TerminalKeypad) -> bool {
+    let mut buffer = [0; 64];
+    let len = io::stdin().read(&mut buffer).unwrap_or(0);
+    let input = &buffer[..len];
+    // A lone Esc, rather than the start of an arrow key's sequence
+    if input == b"\x1b" || input.contains(&0x03) {
+        return false;
+    }
+    for &byte in input {
+        // Escape sequences carry no keypad keys, skip what is left
+        if byte == 0x1b {
+            break;
+        }
+        keypad.typed(byte as char);
+    }
+    true
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}: {}", options.rom, err);
+            process::exit(1);
+        }
+    };
+    let romdb = match RomDb::load() {
+        Ok(romdb) => romdb,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let info = romdb.lookup(&rom);
+    let (platform, quirks, ipf) = rom_settings(info, None, options.ipf, Path::new(&options.rom));
+    let mut chip8 = Chip8::new(quirks)
+        .with_platform(platform)
+        .with_vip_timing(options.vip_timing);
+    if let Some(seed) = options.seed {
+        chip8 = chip8.with_seed(seed);
+    }
+    if let Err(err) = chip8.load(&rom) {
+        eprintln!("{}: {}", options.rom, err);
+        process::exit(1);
+    }
+    let mut scheduler = Scheduler::for_chip8(&chip8, ipf);
+
+    let mut machine = Machine::new(
+        chip8,
+        TerminalDisplay::new(options.glyphs),
+        TerminalBeeper::new(!options.mute),
+        TerminalKeypad::new(&options.keymap),
+    );
+
+    let terminal = match RawTerminal::enter() {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut fault = None;
+    let mut last_frame = Instant::now();
+    'gameloop: loop {
+        if !read_keys(machine.keypad_mut()) {
+            break;
+        }
+
+        let now = Instant::now();
+        let frames = scheduler.advance(now - last_frame);
+        last_frame = now;
+        for _ in 0..frames {
+            machine.keypad_mut().frame();
+            if let Err(err) = machine.run(scheduler.ipf()) {
+                fault = Some(err);
+                break 'gameloop;
+            }
+            machine.timer_tick();
+        }
+
+        if frames > 0 {
+            machine.present();
+            let text = compose(machine.display().lines(), machine.chip8());
+            let mut stdout = io::stdout().lock();
+            if stdout
+                .write_all(text.as_bytes())
+                .and_then(|()| stdout.flush())
+                .is_err()
+            {
+                break;
+            }
+        } else {
+            thread::sleep(scheduler.until_next_frame());
+        }
+    }
+
+    drop(terminal);
+    if let Some(err) = fault {
+        eprintln!("Emulation halted: {}", err);
+        process::exit(1);
+    }
+}
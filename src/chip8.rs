@@ -1,15 +1,36 @@
 use rand::random;
 
+use crate::error::Chip8Error;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
 use crate::register::{IRegister, VRegister};
+use crate::rng::Rng;
 use crate::stack::Stack;
+use crate::state::{StateReader, StateWriter};
+use crate::timing::{vip_cycles, VIP_CYCLES_PER_FRAME};
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 
-const MEM_SIZE: usize = 4096;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 
 const NUM_KEYS: usize = 16;
 
+const NUM_RPL_FLAGS: usize = 16;
+
+const AUDIO_PATTERN_SIZE: usize = 16;
+
+const DEFAULT_PITCH: u8 = 64;
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+
+const STATE_VERSION: u8 = 4;
+
+// Version 1 states lack the random generator, version 2 the VIP timing mode
+// and version 3 the FX0A key wait; all are still accepted
+const OLDEST_STATE_VERSION: u8 = 1;
+
 const START_ADDR: u16 = 0x200;
 
 const FONTSET_SIZE: usize = 80;
@@ -33,8 +54,40 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_FONTSET_ADDR: usize = FONTSET_SIZE;
+
+const BIG_FONTSET_SIZE: usize = 160;
+
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// A data memory access made by the last instructions, recorded while access
+/// logging is on. Instruction fetches are not included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: usize,
+    pub write: bool,
+}
+
 pub struct Chip8 {
-    mem: [u8; MEM_SIZE],
+    mem: Vec<u8>,
+    platform: Platform,
     v_regs: VRegister,
     i_reg: IRegister,
     delay_timer: u8,
@@ -42,28 +95,90 @@ pub struct Chip8 {
     pc: u16,
     stack: Stack,
     keypad: [bool; NUM_KEYS],
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    // FX0A is waiting, and the key released since it started, if any
+    waiting_for_key: bool,
+    released_key: Option<u8>,
+    // Each pixel holds one bit per XO-CHIP bitplane
+    screen: [u8; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+    planes: u8,
+    hires: bool,
+    halted: bool,
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+    quirks: Quirks,
+    vblank: bool,
+    seed: u64,
+    rng: Rng,
+    vip_timing: bool,
+    // Machine cycles left before the next interrupt in VIP timing mode,
+    // negative when the last instruction ran past it
+    cycle_budget: i32,
+    access_log: Option<Vec<MemAccess>>,
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
+    /// Creates a machine with a random seed for CXNN, see `with_seed`.
+    pub fn new(quirks: Quirks) -> Self {
+        let seed = random();
         let mut chip8 = Self {
-            mem: [0; MEM_SIZE],
+            mem: vec![0; Platform::default().memory_size()],
+            platform: Platform::default(),
             v_regs: VRegister::new(),
             i_reg: IRegister::new(),
             delay_timer: 0,
             sound_timer: 0,
             pc: START_ADDR,
             keypad: [false; NUM_KEYS],
+            waiting_for_key: false,
+            released_key: None,
             stack: Stack::new(),
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            screen: [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT],
+            planes: 1,
+            hires: false,
+            halted: false,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            quirks,
+            vblank: false,
+            seed,
+            rng: Rng::new(seed),
+            vip_timing: false,
+            cycle_budget: VIP_CYCLES_PER_FRAME as i32,
+            access_log: None,
         };
-        chip8.mem[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        chip8.load_fonts();
         chip8
     }
 
+    /// Switches to another platform, resizing memory to match. Anything
+    /// already loaded is discarded.
+    pub fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self.reset();
+        self
+    }
+
+    /// Seeds the random generator behind CXNN, making runs with the same
+    /// input repeat exactly.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// Charges every instruction the machine cycles it took on the COSMAC
+    /// VIP, see `timing::vip_cycles`, out of a budget refilled by each
+    /// `timer_tick`. Once the budget is spent, and whenever DXYN waits for
+    /// the interrupt, `tick` does nothing until the next `timer_tick`.
+    pub fn with_vip_timing(mut self, enabled: bool) -> Self {
+        self.vip_timing = enabled;
+        self
+    }
+
     pub fn reset(&mut self) {
-        self.mem = [0; MEM_SIZE];
+        self.mem = vec![0; self.platform.memory_size()];
         self.v_regs.reset();
         self.i_reg.reset();
         self.delay_timer = 0;
@@ -71,15 +186,48 @@ impl Chip8 {
         self.pc = START_ADDR;
         self.stack.reset();
         self.keypad = [false; NUM_KEYS];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-        self.mem[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.waiting_for_key = false;
+        self.released_key = None;
+        self.screen = [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT];
+        self.planes = 1;
+        self.hires = false;
+        self.halted = false;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.vblank = false;
+        self.rng = Rng::new(self.seed);
+        self.cycle_budget = VIP_CYCLES_PER_FRAME as i32;
+        self.load_fonts();
     }
 
-    pub fn tick(&mut self) {
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        // 00FD stops the interpreter for good
+        if self.halted || self.is_stalled() {
+            return Ok(());
+        }
+
         // Fetch
-        let op = self.fetch();
+        let op = self.fetch()?;
+        if self.vip_timing {
+            self.cycle_budget -= vip_cycles(op) as i32;
+            // Any other instruction means a following DXYN has to wait for
+            // the next interrupt
+            if op >> 12 != 0xD {
+                self.vblank = false;
+            }
+        }
         // Decode and Execute
-        self.decode_and_execute(op);
+        self.decode_and_execute(op)
+    }
+
+    pub fn vip_timing(&self) -> bool {
+        self.vip_timing
+    }
+
+    /// True while VIP timing waits for the next interrupt, during which
+    /// `tick` does nothing.
+    pub fn is_stalled(&self) -> bool {
+        self.vip_timing && self.cycle_budget <= 0
     }
 
     pub fn timer_tick(&mut self) {
@@ -91,36 +239,406 @@ impl Chip8 {
             // TODO: Give sound
             self.sound_timer -= 1;
         }
+
+        self.vblank = true;
+        // Unused cycles are lost, an overrun is paid back
+        self.cycle_budget = self.cycle_budget.min(0) + VIP_CYCLES_PER_FRAME as i32;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Pixel values of the visible screen; bit N is set when the pixel is lit
+    /// on bitplane N, giving values 0..=3.
+    pub fn get_screen(&self) -> &[u8] {
+        let (width, height) = self.get_resolution();
+        &self.screen[..width * height]
+    }
+
+    pub fn get_resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    pub fn get_screen(&self) -> &[bool] {
-        &self.screen
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn get_i(&self) -> u16 {
+        self.i_reg.read()
+    }
+
+    pub fn get_v_regs(&self) -> [u8; 16] {
+        self.v_regs.values()
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// Return addresses currently on the stack, oldest first.
+    pub fn get_stack(&self) -> &[u16] {
+        self.stack.entries()
+    }
+
+    pub fn get_memory(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// The opcode at PC, without executing it.
+    pub fn peek_op(&self) -> Result<u16, Chip8Error> {
+        let pc = self.pc as usize;
+        match (self.mem.get(pc), self.mem.get(pc + 1)) {
+            (Some(&high_byte), Some(&low_byte)) => Ok(((high_byte as u16) << 8) + low_byte as u16),
+            _ => Err(Chip8Error::MemoryOutOfBounds { addr: pc }),
+        }
+    }
+
+    /// Starts or stops recording data memory accesses for `take_accesses`.
+    pub fn set_access_logging(&mut self, enabled: bool) {
+        self.access_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns and clears the accesses recorded since the last call.
+    pub fn take_accesses(&mut self) -> Vec<MemAccess> {
+        self.access_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
     }
 
     pub fn get_sound_timer(&self) -> u8 {
         self.sound_timer
     }
 
-    pub fn keypress(&mut self, idx: usize, pressed: bool) {
-        self.keypad[idx] = pressed;
+    /// The XO-CHIP 1-bit audio pattern, once a ROM has loaded one with F002.
+    pub fn get_audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The XO-CHIP pitch register; the pattern plays back at
+    /// 4000 * 2^((pitch - 64) / 48) bits per second.
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Sets the state of a keypad key. Only changes matter, so a frontend
+    /// may call this once per press and release.
+    pub fn keypress(&mut self, idx: usize, pressed: bool) -> Result<(), Chip8Error> {
+        let key = self
+            .keypad
+            .get_mut(idx)
+            .ok_or(Chip8Error::InvalidKey { idx })?;
+        // A release is what ends FX0A
+        if self.waiting_for_key && *key && !pressed {
+            self.released_key = Some(idx as u8);
+        }
+        *key = pressed;
+        Ok(())
     }
 
-    pub fn load(&mut self, data: &[u8]) {
+    pub fn is_key_pressed(&self, idx: usize) -> Result<bool, Chip8Error> {
+        self.keypad
+            .get(idx)
+            .copied()
+            .ok_or(Chip8Error::InvalidKey { idx })
+    }
+
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
         let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
+        let max = self.mem.len() - start;
+        if data.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+
+        let end = start + data.len();
         self.mem[start..end].copy_from_slice(data);
+        Ok(())
     }
 
-    fn fetch(&mut self) -> u16 {
-        // TODO
-        let high_byte: u16 = self.mem[self.pc as usize] as u16;
-        let low_byte: u16 = self.mem[(self.pc + 1) as usize] as u16;
-        let op = (high_byte << 8) + low_byte;
-        self.pc += 2;
-        op
+    fn load_fonts(&mut self) {
+        self.mem[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.mem[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE]
+            .copy_from_slice(&BIG_FONTSET);
+    }
+
+    /// Serializes the whole machine, including platform and quirks, into a
+    /// versioned binary snapshot.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(STATE_MAGIC);
+        writer.write_u8(STATE_VERSION);
+
+        writer.write_u8(self.platform.id());
+        writer.write_u8(self.quirks.to_bits());
+        writer.write_u32(self.mem.len() as u32);
+        writer.write_bytes(&self.mem);
+        self.v_regs.save_state(&mut writer);
+        self.i_reg.save_state(&mut writer);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_u16(self.pc);
+        self.stack.save_state(&mut writer);
+        for key in self.keypad {
+            writer.write_bool(key);
+        }
+        writer.write_bytes(&self.screen);
+        writer.write_u8(self.planes);
+        writer.write_bool(self.hires);
+        writer.write_bool(self.halted);
+        writer.write_bytes(&self.rpl_flags);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+        writer.write_bool(self.vblank);
+        writer.write_u64(self.seed);
+        self.rng.save_state(&mut writer);
+        writer.write_bool(self.vip_timing);
+        writer.write_u32(self.cycle_budget as u32);
+        writer.write_bool(self.waiting_for_key);
+        writer.write_bool(self.released_key.is_some());
+        writer.write_u8(self.released_key.unwrap_or_default());
+
+        writer.into_bytes()
     }
 
-    fn decode_and_execute(&mut self, op: u16) {
+    /// Restores a snapshot taken by `save_state`. On error the machine is left
+    /// untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(Chip8Error::InvalidState {
+                reason: "not a save state",
+            });
+        }
+        let version = reader.read_u8()?;
+        if !(OLDEST_STATE_VERSION..=STATE_VERSION).contains(&version) {
+            return Err(Chip8Error::UnsupportedStateVersion { version });
+        }
+
+        let platform = Platform::from_id(reader.read_u8()?).ok_or(Chip8Error::InvalidState {
+            reason: "unknown platform",
+        })?;
+        let quirks = Quirks::from_bits(reader.read_u8()?);
+        let mut chip8 = Chip8::new(quirks).with_platform(platform);
+
+        let mem_size = reader.read_u32()? as usize;
+        if mem_size != chip8.mem.len() {
+            return Err(Chip8Error::InvalidState {
+                reason: "memory size does not match platform",
+            });
+        }
+        chip8.mem.copy_from_slice(reader.read_bytes(mem_size)?);
+        chip8.v_regs.load_state(&mut reader)?;
+        chip8.i_reg.load_state(&mut reader)?;
+        chip8.delay_timer = reader.read_u8()?;
+        chip8.sound_timer = reader.read_u8()?;
+        chip8.pc = reader.read_u16()?;
+        chip8.stack.load_state(&mut reader)?;
+        for key in chip8.keypad.iter_mut() {
+            *key = reader.read_bool()?;
+        }
+        chip8
+            .screen
+            .copy_from_slice(reader.read_bytes(HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT)?);
+        chip8.planes = reader.read_u8()?;
+        chip8.hires = reader.read_bool()?;
+        chip8.halted = reader.read_bool()?;
+        chip8
+            .rpl_flags
+            .copy_from_slice(reader.read_bytes(NUM_RPL_FLAGS)?);
+        let has_pattern = reader.read_bool()?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(reader.read_bytes(AUDIO_PATTERN_SIZE)?);
+        chip8.audio_pattern = if has_pattern { Some(pattern) } else { None };
+        chip8.pitch = reader.read_u8()?;
+        chip8.vblank = reader.read_bool()?;
+        if version >= 2 {
+            chip8.seed = reader.read_u64()?;
+            chip8.rng.load_state(&mut reader)?;
+        }
+        if version >= 3 {
+            chip8.vip_timing = reader.read_bool()?;
+            chip8.cycle_budget = reader.read_u32()? as i32;
+        }
+        if version >= 4 {
+            chip8.waiting_for_key = reader.read_bool()?;
+            let has_released = reader.read_bool()?;
+            let released = reader.read_u8()?;
+            chip8.released_key = if has_released { Some(released) } else { None };
+        }
+
+        if !reader.is_empty() {
+            return Err(Chip8Error::InvalidState {
+                reason: "trailing data",
+            });
+        }
+
+        chip8.access_log = self.access_log.take();
+        *self = chip8;
+        Ok(())
+    }
+
+    fn is_xochip(&self) -> bool {
+        self.platform == Platform::XoChip
+    }
+
+    /// Clears the selected bitplanes.
+    fn clear_screen(&mut self) {
+        for pixel in self.screen.iter_mut() {
+            *pixel &= !self.planes;
+        }
+    }
+
+    /// Moves the selected bitplanes of the visible screen by (dx, dy) pixels,
+    /// filling with blanks.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.get_resolution();
+        let old = self.screen;
+        for y in 0..height {
+            for x in 0..width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let inside =
+                    (0..width as isize).contains(&src_x) && (0..height as isize).contains(&src_y);
+                let moved = if inside {
+                    old[src_x as usize + width * src_y as usize] & self.planes
+                } else {
+                    0
+                };
+                let idx = x + width * y;
+                self.screen[idx] = (self.screen[idx] & !self.planes) | moved;
+            }
+        }
+    }
+
+    /// XORs a sprite of `rows` rows, 8 or 16 pixels wide, read from I onto each
+    /// selected bitplane and reports whether any lit pixel was erased. Every
+    /// plane reads its own copy of the sprite data, one after the other.
+    fn draw_sprite(
+        &mut self,
+        x_coord: usize,
+        y_coord: usize,
+        rows: usize,
+        wide: bool,
+    ) -> Result<bool, Chip8Error> {
+        let (width, height) = self.get_resolution();
+        let bytes_per_row = if wide { 2 } else { 1 };
+
+        // The origin always wraps
+        let x_coord = x_coord % width;
+        let y_coord = y_coord % height;
+
+        // keep track of collision
+        let mut collision: bool = false;
+
+        let mut addr = self.i_reg.read() as usize;
+        for plane in 0..2 {
+            let plane_bit = 1 << plane;
+            if self.planes & plane_bit == 0 {
+                continue;
+            }
+
+            for y_line in 0..rows {
+                let mut pixels: u16 = 0;
+                for _ in 0..bytes_per_row {
+                    pixels = (pixels << 8) | self.read_mem(addr)? as u16;
+                    addr += 1;
+                }
+
+                // Iterate over row; length is 8 or 16
+                let row_width = 8 * bytes_per_row;
+                for x_line in 0..row_width {
+                    if (pixels >> (row_width - 1 - x_line)) & 1 == 0 {
+                        continue;
+                    }
+
+                    let x = x_coord + x_line;
+                    let y = y_coord + y_line;
+                    if self.quirks.clip_sprites && (x >= width || y >= height) {
+                        continue;
+                    }
+
+                    let idx = (x % width) + width * (y % height);
+                    collision |= self.screen[idx] & plane_bit != 0;
+                    self.screen[idx] ^= plane_bit;
+                }
+            }
+        }
+
+        Ok(collision)
+    }
+
+    /// Skips the next instruction, which on XO-CHIP may be the 4 byte F000 NNNN.
+    fn skip(&mut self) -> Result<(), Chip8Error> {
+        let next = self.peek_op()?;
+        self.advance_pc(if self.is_xochip() && next == 0xF000 {
+            4
+        } else {
+            2
+        })
+    }
+
+    /// Moves PC forward, failing instead of wrapping when a program runs
+    /// off the end of XO-CHIP's 64K memory.
+    fn advance_pc(&mut self, bytes: u16) -> Result<(), Chip8Error> {
+        self.pc = self
+            .pc
+            .checked_add(bytes)
+            .ok_or(Chip8Error::MemoryOutOfBounds {
+                addr: self.pc as usize + bytes as usize,
+            })?;
+        Ok(())
+    }
+
+    fn read_mem(&mut self, addr: usize) -> Result<u8, Chip8Error> {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push(MemAccess { addr, write: false });
+        }
+        self.mem
+            .get(addr)
+            .copied()
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr })
+    }
+
+    fn write_mem(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push(MemAccess { addr, write: true });
+        }
+        let byte = self
+            .mem
+            .get_mut(addr)
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr })?;
+        *byte = value;
+        Ok(())
+    }
+
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let op = self.peek_op()?;
+        self.advance_pc(2)?;
+        Ok(op)
+    }
+
+    fn decode_and_execute(&mut self, op: u16) -> Result<(), Chip8Error> {
         // 0xABCD -> digit0 digit1 digit2 digit3
         let digit0 = (op & 0xF000) >> 12;
         let digit1 = (op & 0x0F00) >> 8;
@@ -129,11 +647,36 @@ impl Chip8 {
 
         // Decode and Execute
         match (digit0, digit1, digit2, digit3) {
+            (0, 0, 0xC, _) => {
+                let n = digit3 as isize;
+                self.scroll(0, n);
+            }
+            (0, 0, 0xD, _) if self.is_xochip() => {
+                let n = digit3 as isize;
+                self.scroll(0, -n);
+            }
             (0, 0, 0xE, 0) => {
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+                self.clear_screen();
             }
             (0, 0, 0xE, 0xE) => {
-                self.pc = self.stack.pop();
+                self.pc = self.stack.pop()?;
+            }
+            (0, 0, 0xF, 0xB) => {
+                self.scroll(4, 0);
+            }
+            (0, 0, 0xF, 0xC) => {
+                self.scroll(-4, 0);
+            }
+            (0, 0, 0xF, 0xD) => {
+                self.halted = true;
+            }
+            (0, 0, 0xF, 0xE) => {
+                self.hires = false;
+                self.screen = [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT];
+            }
+            (0, 0, 0xF, 0xF) => {
+                self.hires = true;
+                self.screen = [0; HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT];
             }
             (1, _, _, _) => {
                 let nnn = op & 0xFFF;
@@ -141,105 +684,138 @@ impl Chip8 {
             }
             (2, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.stack.push(self.pc);
+                self.stack.push(self.pc)?;
                 self.pc = nnn;
             }
             (3, _, _, _) => {
                 let x = digit1 as usize;
                 let kk = (op & 0xFF) as u8;
-                if self.v_regs.read(x) == kk {
-                    self.pc += 2;
+                if self.v_regs.read(x)? == kk {
+                    self.skip()?;
                 }
             }
             (4, _, _, _) => {
                 let x = digit1 as usize;
                 let kk = (op & 0xFF) as u8;
-                if self.v_regs.read(x) != kk {
-                    self.pc += 2;
+                if self.v_regs.read(x)? != kk {
+                    self.skip()?;
                 }
             }
             (5, _, _, 0) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                if self.v_regs.read(x) == self.v_regs.read(y) {
-                    self.pc += 2;
+                if self.v_regs.read(x)? == self.v_regs.read(y)? {
+                    self.skip()?;
+                }
+            }
+            (5, _, _, 2) if self.is_xochip() => {
+                let x = digit1 as usize;
+                let y = digit2 as usize;
+                let i = self.i_reg.read() as usize;
+                for (offset, idx) in register_range(x, y).into_iter().enumerate() {
+                    self.write_mem(i + offset, self.v_regs.read(idx)?)?;
+                }
+            }
+            (5, _, _, 3) if self.is_xochip() => {
+                let x = digit1 as usize;
+                let y = digit2 as usize;
+                let i = self.i_reg.read() as usize;
+                for (offset, idx) in register_range(x, y).into_iter().enumerate() {
+                    let value = self.read_mem(i + offset)?;
+                    self.v_regs.write(idx, value)?;
                 }
             }
             (6, _, _, _) => {
                 let x = digit1 as usize;
                 let kk = (op & 0xFF) as u8;
-                self.v_regs.write(x, kk);
+                self.v_regs.write(x, kk)?;
             }
             (7, _, _, _) => {
                 let x = digit1 as usize;
                 let kk = (op & 0xFF) as u8;
-                let value = self.v_regs.read(x).wrapping_add(kk);
-                self.v_regs.write(x, value);
+                let value = self.v_regs.read(x)?.wrapping_add(kk);
+                self.v_regs.write(x, value)?;
             }
             (8, _, _, 0) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                self.v_regs.write(x, self.v_regs.read(y));
+                self.v_regs.write(x, self.v_regs.read(y)?)?;
             }
             (8, _, _, 1) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let value = self.v_regs.read(x) | self.v_regs.read(y);
-                self.v_regs.write(x, value);
+                let value = self.v_regs.read(x)? | self.v_regs.read(y)?;
+                self.v_regs.write(x, value)?;
+                if self.quirks.logic_resets_vf {
+                    self.v_regs.write(0xF, 0)?;
+                }
             }
             (8, _, _, 2) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let value = self.v_regs.read(x) & self.v_regs.read(y);
-                self.v_regs.write(x, value);
+                let value = self.v_regs.read(x)? & self.v_regs.read(y)?;
+                self.v_regs.write(x, value)?;
+                if self.quirks.logic_resets_vf {
+                    self.v_regs.write(0xF, 0)?;
+                }
             }
             (8, _, _, 3) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let value = self.v_regs.read(x) ^ self.v_regs.read(y);
-                self.v_regs.write(x, value);
+                let value = self.v_regs.read(x)? ^ self.v_regs.read(y)?;
+                self.v_regs.write(x, value)?;
+                if self.quirks.logic_resets_vf {
+                    self.v_regs.write(0xF, 0)?;
+                }
             }
             (8, _, _, 4) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let (new_vx, carry_bit) = self.v_regs.read(x).overflowing_add(self.v_regs.read(y));
+                let (new_vx, carry_bit) =
+                    self.v_regs.read(x)?.overflowing_add(self.v_regs.read(y)?);
                 let new_vf = if carry_bit { 1 } else { 0 };
-                self.v_regs.write(x, new_vx);
-                self.v_regs.write(0xF, new_vf);
+                self.v_regs.write(x, new_vx)?;
+                self.v_regs.write(0xF, new_vf)?;
             }
             (8, _, _, 5) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let (new_vx, carry_bit) = self.v_regs.read(x).overflowing_sub(self.v_regs.read(y));
+                let (new_vx, carry_bit) =
+                    self.v_regs.read(x)?.overflowing_sub(self.v_regs.read(y)?);
                 let new_vf = if carry_bit { 0 } else { 1 };
-                self.v_regs.write(x, new_vx);
-                self.v_regs.write(0xF, new_vf);
+                self.v_regs.write(x, new_vx)?;
+                self.v_regs.write(0xF, new_vf)?;
             }
             (8, _, _, 6) => {
                 let x = digit1 as usize;
-                let lsb = self.v_regs.read(x) & 1;
-                self.v_regs.write(x, self.v_regs.read(x) >> 1);
-                self.v_regs.write(0xF, lsb);
+                let y = digit2 as usize;
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let lsb = self.v_regs.read(src)? & 1;
+                self.v_regs.write(x, self.v_regs.read(src)? >> 1)?;
+                self.v_regs.write(0xF, lsb)?;
             }
             (8, _, _, 7) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                let (new_vx, carry_bit) = self.v_regs.read(y).overflowing_sub(self.v_regs.read(x));
+                let (new_vx, carry_bit) =
+                    self.v_regs.read(y)?.overflowing_sub(self.v_regs.read(x)?);
                 let new_vf = if carry_bit { 0 } else { 1 };
-                self.v_regs.write(x, new_vx);
-                self.v_regs.write(0xF, new_vf);
+                self.v_regs.write(x, new_vx)?;
+                self.v_regs.write(0xF, new_vf)?;
             }
             (8, _, _, 0xE) => {
                 let x = digit1 as usize;
-                let msb = (self.v_regs.read(x) >> 7) & 1;
-                self.v_regs.write(x, self.v_regs.read(x) << 1);
-                self.v_regs.write(0xF, msb);
+                let y = digit2 as usize;
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let msb = (self.v_regs.read(src)? >> 7) & 1;
+                self.v_regs.write(x, self.v_regs.read(src)? << 1)?;
+                self.v_regs.write(0xF, msb)?;
             }
             (9, _, _, 0) => {
                 let x = digit1 as usize;
                 let y = digit2 as usize;
-                if self.v_regs.read(x) != self.v_regs.read(y) {
-                    self.pc += 2;
+                if self.v_regs.read(x)? != self.v_regs.read(y)? {
+                    self.skip()?;
                 }
             }
             (0xA, _, _, _) => {
@@ -248,158 +824,755 @@ impl Chip8 {
             }
             (0xB, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.pc = (self.v_regs.read(0) as u16) + nnn;
+                let x = if self.quirks.jump_uses_vx {
+                    digit1 as usize
+                } else {
+                    0
+                };
+                self.pc = (self.v_regs.read(x)? as u16) + nnn;
             }
             (0xC, _, _, _) => {
                 let x = digit1 as usize;
                 let kk = (op & 0xFF) as u8;
-                let random_num: u8 = random();
-                self.v_regs.write(x, random_num & kk);
+                let random_num = self.rng.next_u8();
+                self.v_regs.write(x, random_num & kk)?;
             }
             // Draw
             (0xD, _, _, _) => {
-                // Get coords from v registers
-                let x_coord = self.v_regs.read(digit1 as usize) as u16;
-                let y_coord = self.v_regs.read(digit2 as usize) as u16;
-
-                // num of rows
-                let n = digit3;
-
-                // keep track of collision
-                let mut collision: bool = false;
-
-                for y_line in 0..n {
-                    let current_addr = self.i_reg.read() + y_line;
-                    let pixels = self.mem[current_addr as usize];
-
-                    // Iterate over row; length is 8
-                    for x_line in 0..8 {
-                        if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                            let x = (x_coord + x_line) as usize % SCREEN_WIDTH;
-                            let y = (y_coord + y_line) as usize % SCREEN_HEIGHT;
-
-                            let idx = x + SCREEN_WIDTH * y;
-                            collision |= self.screen[idx];
-                            self.screen[idx] ^= true;
+                // Wait for the vertical blank by repeating the opcode
+                if self.quirks.display_wait || self.vip_timing {
+                    if !self.vblank {
+                        self.pc -= 2;
+                        // The VIP sleeps until the interrupt
+                        if self.vip_timing {
+                            self.cycle_budget = 0;
                         }
+                        return Ok(());
                     }
+                    self.vblank = false;
                 }
-                self.v_regs.write(0xF, if collision { 1 } else { 0 });
+
+                // Get coords from v registers
+                let x_coord = self.v_regs.read(digit1 as usize)? as usize;
+                let y_coord = self.v_regs.read(digit2 as usize)? as usize;
+
+                // DXY0 draws a 16x16 SUPER-CHIP sprite
+                let collision = if digit3 == 0 {
+                    self.draw_sprite(x_coord, y_coord, 16, true)?
+                } else {
+                    self.draw_sprite(x_coord, y_coord, digit3 as usize, false)?
+                };
+                self.v_regs.write(0xF, if collision { 1 } else { 0 })?;
             }
             (0xE, _, 9, 0xE) => {
                 let x = digit1 as usize;
-                let key: bool = self.keypad[self.v_regs.read(x) as usize];
+                // Only the low nibble selects a key, as on the VIP
+                let key: bool = self.keypad[(self.v_regs.read(x)? & 0xF) as usize];
                 if key {
-                    self.pc += 2;
+                    self.skip()?;
                 }
             }
             (0xE, _, 0xA, 1) => {
                 let x = digit1 as usize;
-                let key: bool = self.keypad[self.v_regs.read(x) as usize];
+                let key: bool = self.keypad[(self.v_regs.read(x)? & 0xF) as usize];
                 if !key {
-                    self.pc += 2;
+                    self.skip()?;
+                }
+            }
+            (0xF, 0, 0, 0) if self.is_xochip() => {
+                let nnnn = self.peek_op()?;
+                self.i_reg.write(nnnn);
+                self.advance_pc(2)?;
+            }
+            (0xF, _, 0, 1) if self.is_xochip() => {
+                self.planes = digit1 as u8 & 0b11;
+            }
+            (0xF, 0, 0, 2) if self.is_xochip() => {
+                let i = self.i_reg.read() as usize;
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_mem(i + offset)?;
                 }
+                self.audio_pattern = Some(pattern);
             }
             (0xF, _, 0, 7) => {
                 let x = digit1 as usize;
-                self.v_regs.write(x, self.delay_timer);
+                self.v_regs.write(x, self.delay_timer)?;
             }
             (0xF, _, 0, 0xA) => {
                 let x = digit1 as usize;
-                let mut pressed = false;
-
-                for i in 0..self.keypad.len() {
-                    let key = self.keypad[i];
-                    if key {
-                        self.v_regs.write(x, i as u8);
-                        pressed = true;
-                        break;
-                    }
-                }
 
-                // repeat opcode by decreasing it by 2 (reversing fetch process)
-                if !pressed {
+                // As on the VIP, a key counts once it is pressed and released
+                // again, so holding it down does not read it twice
+                if let Some(key) = self.released_key.take() {
+                    self.v_regs.write(x, key)?;
+                    self.waiting_for_key = false;
+                } else {
+                    self.waiting_for_key = true;
+                    // repeat opcode by decreasing it by 2 (reversing fetch process)
                     self.pc -= 2;
                 }
             }
             (0xF, _, 1, 5) => {
                 let x = digit1 as usize;
-                self.delay_timer = self.v_regs.read(x);
+                self.delay_timer = self.v_regs.read(x)?;
             }
             (0xF, _, 1, 8) => {
                 let x = digit1 as usize;
-                self.sound_timer = self.v_regs.read(x);
+                self.sound_timer = self.v_regs.read(x)?;
             }
             (0xF, _, 1, 0xE) => {
                 let x = digit1 as usize;
-                let vx = self.v_regs.read(x) as u16;
+                let vx = self.v_regs.read(x)? as u16;
                 let value = self.i_reg.read().wrapping_add(vx);
                 self.i_reg.write(value);
             }
             (0xF, _, 2, 9) => {
                 let x = digit1 as usize;
-                let char = self.v_regs.read(x) as u16;
+                let char = (self.v_regs.read(x)? & 0xF) as u16;
                 self.i_reg.write(char * 5);
             }
+            (0xF, _, 3, 0xA) if self.is_xochip() => {
+                let x = digit1 as usize;
+                self.pitch = self.v_regs.read(x)?;
+            }
+            (0xF, _, 3, 0) => {
+                let x = digit1 as usize;
+                let char = (self.v_regs.read(x)? & 0xF) as usize;
+                self.i_reg.write((BIG_FONTSET_ADDR + char * 10) as u16);
+            }
             (0xF, _, 3, 3) => {
                 let x = digit1 as usize;
-                let value = self.v_regs.read(x) as f32;
+                let value = self.v_regs.read(x)? as f32;
 
                 let hundreds = (value / 100.0).floor() as u8;
                 let tens = ((value / 10.0) % 10.0).floor() as u8;
                 let ones = (value % 10.0) as u8;
                 let idx = self.i_reg.read() as usize;
 
-                self.mem[idx] = hundreds;
-                self.mem[idx + 1] = tens;
-                self.mem[idx + 2] = ones;
+                self.write_mem(idx, hundreds)?;
+                self.write_mem(idx + 1, tens)?;
+                self.write_mem(idx + 2, ones)?;
             }
             (0xF, _, 5, 5) => {
                 let x = digit1 as usize;
                 let i = self.i_reg.read() as usize;
                 for idx in 0..=x {
-                    self.mem[i + idx] = self.v_regs.read(idx);
+                    self.write_mem(i + idx, self.v_regs.read(idx)?)?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i_reg.write((i + x + 1) as u16);
                 }
             }
             (0xF, _, 6, 5) => {
                 let x = digit1 as usize;
                 let i = self.i_reg.read() as usize;
                 for idx in 0..=x {
-                    let value = self.mem[i + idx];
-                    self.v_regs.write(idx, value);
+                    let value = self.read_mem(i + idx)?;
+                    self.v_regs.write(idx, value)?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i_reg.write((i + x + 1) as u16);
                 }
             }
-            (_, _, _, _) => unimplemented!("Instruction not implemented!"),
+            (0xF, _, 7, 5) => {
+                let x = digit1 as usize;
+                for idx in 0..=x {
+                    self.rpl_flags[idx] = self.v_regs.read(idx)?;
+                }
+            }
+            (0xF, _, 8, 5) => {
+                let x = digit1 as usize;
+                for idx in 0..=x {
+                    self.v_regs.write(idx, self.rpl_flags[idx])?;
+                }
+            }
+            (_, _, _, _) => {
+                return Err(Chip8Error::UnknownOpcode {
+                    pc: self.pc - 2,
+                    op,
+                })
+            }
         }
+
+        Ok(())
+    }
+}
+
+/// Registers VX through VY for 5XY2/5XY3, in descending order when X > Y.
+fn register_range(x: usize, y: usize) -> Vec<usize> {
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
     }
 }
 
 impl Default for Chip8 {
     fn default() -> Self {
-        Self::new()
+        Self::new(Quirks::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Chip8;
+    use crate::error::Chip8Error;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use crate::timing::VIP_MAX_IPF;
 
     #[test]
     fn test_new_chip8() {
-        let chip8: Chip8 = Chip8::new();
+        let chip8: Chip8 = Chip8::new(Quirks::default());
         assert_eq!(chip8.pc, 0x200);
     }
 
     #[test]
     fn test_push_and_pop() {
-        let mut chip8: Chip8 = Chip8::new();
-        chip8.stack.push(1);
-        chip8.stack.push(2);
+        let mut chip8: Chip8 = Chip8::new(Quirks::default());
+        chip8.stack.push(1).unwrap();
+        chip8.stack.push(2).unwrap();
 
         assert_eq!(chip8.stack.get(0), 1);
         assert_eq!(chip8.stack.get(1), 2);
 
-        assert_eq!(chip8.stack.pop(), 2);
-        assert_eq!(chip8.stack.pop(), 1);
+        assert_eq!(chip8.stack.pop().unwrap(), 2);
+        assert_eq!(chip8.stack.pop().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        // 6005 6103 8016
+        let rom = [0x60, 0x05, 0x61, 0x03, 0x80, 0x16];
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.v_regs.read(0).unwrap(), 2);
+        assert_eq!(chip8.v_regs.read(0xF).unwrap(), 1);
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.v_regs.read(0).unwrap(), 1);
+        assert_eq!(chip8.v_regs.read(0xF).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        // 6004 6210 B210
+        let rom = [0x60, 0x04, 0x62, 0x10, 0xB2, 0x10];
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.pc, 0x214);
+
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.pc, 0x220);
+    }
+
+    #[test]
+    fn test_load_store_quirk() {
+        // A300 F255
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.i_reg.read(), 0x300);
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.i_reg.read(), 0x303);
+    }
+
+    #[test]
+    fn test_logic_vf_quirk() {
+        // 6F05 8011
+        let rom = [0x6F, 0x05, 0x80, 0x11];
+
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.v_regs.read(0xF).unwrap(), 5);
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.v_regs.read(0xF).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        // 603E A000 D011 (top row of the "0" glyph at x = 62)
+        let rom = [0x60, 0x3E, 0xA0, 0x00, 0xD0, 0x11];
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.screen[63], 1);
+        assert_eq!(chip8.screen[0], 1);
+
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.screen[63], 1);
+        assert_eq!(chip8.screen[0], 0);
+    }
+
+    #[test]
+    fn test_display_wait_quirk() {
+        // A000 D001
+        let rom = [0xA0, 0x00, 0xD0, 0x01];
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.screen[0], 0);
+
+        chip8.timer_tick();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.screen[0], 1);
+    }
+
+    #[test]
+    fn test_key_wait() {
+        // F50A: a key held down is only read once it is released
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0xF5, 0x0A]).unwrap();
+        chip8.keypress(3, true).unwrap();
+        chip8.tick().unwrap();
+        chip8.keypress(3, true).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x200);
+
+        // Releasing a key that is not down does nothing
+        chip8.keypress(9, false).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x200);
+
+        chip8.keypress(3, false).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.v_regs.read(5).unwrap(), 3);
+
+        assert_eq!(
+            chip8.keypress(16, true),
+            Err(Chip8Error::InvalidKey { idx: 16 })
+        );
+        assert_eq!(
+            chip8.is_key_pressed(16),
+            Err(Chip8Error::InvalidKey { idx: 16 })
+        );
+    }
+
+    #[test]
+    fn test_vip_timing() {
+        // 7001 1200: 22 machine cycles per loop
+        let mut chip8 = Chip8::new(Quirks::default()).with_vip_timing(true);
+        chip8.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        for _ in 0..VIP_MAX_IPF {
+            chip8.tick().unwrap();
+        }
+        assert!(chip8.is_stalled());
+        assert_eq!(chip8.v_regs.read(0).unwrap(), 167);
+        chip8.timer_tick();
+        assert!(!chip8.is_stalled());
+
+        // A000 D001 7101 1202: every sprite waits for the next interrupt
+        let mut chip8 = Chip8::new(Quirks::default()).with_vip_timing(true);
+        chip8
+            .load(&[0xA0, 0x00, 0xD0, 0x01, 0x71, 0x01, 0x12, 0x02])
+            .unwrap();
+        for _ in 0..3 {
+            for _ in 0..VIP_MAX_IPF {
+                chip8.tick().unwrap();
+            }
+            chip8.timer_tick();
+        }
+        assert_eq!(chip8.v_regs.read(1).unwrap(), 2);
+        assert_eq!(chip8.pc, 0x202);
+
+        let mut restored = Chip8::new(Quirks::default());
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert!(restored.vip_timing());
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0x00, 0x00, 0xFF, 0xFF]).unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                op: 0x0000
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow_and_underflow() {
+        // 2200: call itself forever
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0x22, 0x00]).unwrap();
+        for _ in 0..16 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.tick(), Err(Chip8Error::StackOverflow));
+
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip8.tick(), Err(Chip8Error::StackUnderflow));
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        // AFFF F155
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0xAF, 0xFF, 0xF1, 0x55]).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        assert!(chip8.load(&[0; 3584]).is_ok());
+        assert_eq!(
+            chip8.load(&[0; 3585]),
+            Err(Chip8Error::RomTooLarge {
+                size: 3585,
+                max: 3584
+            })
+        );
+    }
+
+    #[test]
+    fn test_hires_and_exit() {
+        // 00FF 00FD
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&[0x00, 0xFF, 0x00, 0xFD]).unwrap();
+        assert_eq!(chip8.get_resolution(), (64, 32));
+        chip8.tick().unwrap();
+        assert_eq!(chip8.get_resolution(), (128, 64));
+        assert_eq!(chip8.get_screen().len(), 128 * 64);
+
+        chip8.tick().unwrap();
+        assert!(chip8.is_halted());
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn test_big_sprite_and_scroll() {
+        // 00FF A000 D000 00C1 00FB
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8
+            .load(&[0x00, 0xFF, 0xA0, 0x00, 0xD0, 0x00, 0x00, 0xC1, 0x00, 0xFB])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        // First row of the font memory is F0 90, i.e. "1111 0000 1001 0000"
+        assert_eq!(chip8.screen[3], 1);
+        assert_eq!(chip8.screen[4], 0);
+        assert_eq!(chip8.screen[8], 1);
+
+        chip8.tick().unwrap();
+        assert_eq!(chip8.screen[3], 0);
+        assert_eq!(chip8.screen[128 + 3], 1);
+
+        chip8.tick().unwrap();
+        assert_eq!(chip8.screen[128 + 7], 1);
+        assert_eq!(chip8.screen[128 + 8], 0);
+    }
+
+    #[test]
+    fn test_xochip_scroll_up() {
+        // A000 D005 00D1
+        let rom = [0xA0, 0x00, 0xD0, 0x05, 0x00, 0xD1];
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        // The "0" glyph F0 90 90 90 F0 moves up a row, losing its top
+        assert_eq!(chip8.screen[1], 0);
+        assert_eq!(chip8.screen[3], 1);
+        assert_eq!(chip8.screen[64 * 3 + 1], 1);
+        assert_eq!(chip8.screen[64 * 4 + 1], 0);
+
+        // Not a SUPER-CHIP instruction
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom[4..]).unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                op: 0x00D1
+            })
+        );
+    }
+
+    #[test]
+    fn test_xochip_end_of_memory() {
+        // 6000 as the last instruction, then 3000 skipping it and F000
+        // reading it as an address, all leaving PC past 64K
+        for (pc, op) in [
+            (0xFFFE, [0x60, 0x00]),
+            (0xFFFC, [0x30, 0x00]),
+            (0xFFFC, [0xF0, 0x00]),
+        ] {
+            let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+            chip8.mem[0xFFFE] = 0x60;
+            chip8.mem[pc..pc + 2].copy_from_slice(&op);
+            chip8.pc = pc as u16;
+            assert_eq!(
+                chip8.tick(),
+                Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 })
+            );
+        }
+    }
+
+    #[test]
+    fn test_big_font_and_rpl_flags() {
+        // 6A01 FA30 6007 F175 6000 F185
+        let rom = [
+            0x6A, 0x01, 0xFA, 0x30, 0x60, 0x07, 0xF1, 0x75, 0x60, 0x00, 0xF1, 0x85,
+        ];
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.i_reg.read(), 0x50 + 10);
+
+        for _ in 0..4 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.v_regs.read(0).unwrap(), 7);
+
+        // 6A1B FA29: both fonts only look at the low digit
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load(&[0x6A, 0x1B, 0xFA, 0x29]).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.i_reg.read(), 0xB * 5);
+    }
+
+    #[test]
+    fn test_xochip_long_load_and_skip() {
+        // 3000 F000 1234 6001
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01];
+
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        assert_eq!(chip8.mem.len(), 65536);
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x206);
+
+        chip8.pc = 0x202;
+        chip8.tick().unwrap();
+        assert_eq!(chip8.i_reg.read(), 0x1234);
+        assert_eq!(chip8.pc, 0x206);
+
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom[2..]).unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                op: 0xF000
+            })
+        );
+    }
+
+    #[test]
+    fn test_xochip_planes() {
+        // F301 A000 D001 F201 00E0
+        let rom = [0xF3, 0x01, 0xA0, 0x00, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0];
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        chip8.load(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        // Plane 1 draws F0 from the "0" glyph, plane 2 the following 90
+        assert_eq!(chip8.get_screen()[..4], [3, 1, 1, 3]);
+
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.get_screen()[..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_xochip_register_range() {
+        // 6001 6102 6203 A300 5022 6000 6100 6200 5203
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xA3, 0x00, 0x50, 0x22, 0x60, 0x00, 0x61, 0x00,
+            0x62, 0x00, 0x52, 0x03,
+        ];
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        chip8.load(&rom).unwrap();
+        for _ in 0..5 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.mem[0x300..0x303], [1, 2, 3]);
+
+        for _ in 0..4 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.v_regs.read(2).unwrap(), 1);
+        assert_eq!(chip8.v_regs.read(1).unwrap(), 2);
+        assert_eq!(chip8.v_regs.read(0).unwrap(), 3);
+        assert_eq!(chip8.i_reg.read(), 0x300);
+    }
+
+    #[test]
+    fn test_xochip_audio() {
+        // A000 F002 6070 F03A
+        let rom = [0xA0, 0x00, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        chip8.load(&rom).unwrap();
+        assert_eq!(chip8.get_audio_pattern(), None);
+        assert_eq!(chip8.get_pitch(), 64);
+
+        for _ in 0..4 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(
+            chip8.get_audio_pattern().unwrap()[..5],
+            [0xF0, 0x90, 0x90, 0x90, 0xF0]
+        );
+        assert_eq!(chip8.get_pitch(), 0x70);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        // 6005 2206 1204 A000 D015 00EE
+        let rom = [
+            0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0xA0, 0x00, 0xD0, 0x15, 0x00, 0xEE,
+        ];
+        let mut chip8 = Chip8::new(Quirks::vip());
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        chip8.keypress(3, true).unwrap();
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new(Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.quirks(), Quirks::vip());
+        assert_eq!(restored.pc, 0x208);
+        assert_eq!(restored.stack.get(0), 0x204);
+        assert!(restored.keypad[3]);
+        assert_eq!(restored.save_state(), state);
+
+        // Both machines keep running identically
+        for _ in 0..4 {
+            chip8.tick().unwrap();
+            restored.tick().unwrap();
+            chip8.timer_tick();
+            restored.timer_tick();
+        }
+        assert_eq!(restored.save_state(), chip8.save_state());
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        // C0FF 1200: fill V0 with random bytes
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let mut rolls = Vec::new();
+        for _ in 0..2 {
+            let mut chip8 = Chip8::new(Quirks::default()).with_seed(7);
+            chip8.load(&rom).unwrap();
+            let mut values = Vec::new();
+            for _ in 0..8 {
+                chip8.tick().unwrap();
+                chip8.tick().unwrap();
+                values.push(chip8.get_v_regs()[0]);
+            }
+            rolls.push(values);
+        }
+        assert_eq!(rolls[0], rolls[1]);
+
+        // The generator's position is part of the state, the seed survives reset
+        let mut chip8 = Chip8::new(Quirks::default()).with_seed(7);
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        let state = chip8.save_state();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        let next = chip8.get_v_regs()[0];
+        chip8.load_state(&state).unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.get_v_regs()[0], next);
+        assert_eq!(chip8.seed(), 7);
+
+        chip8.reset();
+        chip8.load(&rom).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.get_v_regs()[0], rolls[0][0]);
+    }
+
+    #[test]
+    fn test_load_version_1_state() {
+        let chip8 = Chip8::new(Quirks::default()).with_seed(3);
+        let mut state = chip8.save_state();
+        // Version 1 ended before the seed, generator state, VIP timing and
+        // key wait
+        state.truncate(state.len() - 24);
+        state[4] = 1;
+
+        let mut restored = Chip8::new(Quirks::default());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.get_pc(), 0x200);
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let mut state = chip8.save_state();
+
+        assert_eq!(
+            chip8.load_state(&state[..state.len() - 1]),
+            Err(Chip8Error::InvalidState {
+                reason: "unexpected end of data"
+            })
+        );
+
+        state[4] = 99;
+        assert_eq!(
+            chip8.load_state(&state),
+            Err(Chip8Error::UnsupportedStateVersion { version: 99 })
+        );
     }
 }
@@ -0,0 +1,11 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where user configuration lives: `$XDG_CONFIG_HOME/chip8`, or
+/// `~/.config/chip8` when that is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("chip8"))
+}
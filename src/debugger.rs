@@ -0,0 +1,629 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::error::Chip8Error;
+
+const HELP: &str = "\
+c, continue         resume execution
+s, step [N]         execute N instructions (default 1)
+n, next             step over a 2NNN call
+f, finish           run until the current subroutine returns
+b, break ADDR       set a breakpoint
+d, delete ADDR      remove a breakpoint
+watch ADDR          stop when ADDR is written
+rwatch ADDR         stop when ADDR is read
+vwatch X            stop when VX changes
+unwatch ADDR | vX   remove a watchpoint
+info                list breakpoints and watchpoints
+r, regs             show registers, timers and stack
+m, mem ADDR [LEN]   dump memory
+q, quit             exit the emulator
+All numbers are hexadecimal.";
+
+/// Why the debugger paused execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DebugEvent {
+    Breakpoint {
+        pc: u16,
+    },
+    MemoryRead {
+        pc: u16,
+        addr: usize,
+    },
+    MemoryWrite {
+        pc: u16,
+        addr: usize,
+    },
+    RegisterChanged {
+        pc: u16,
+        idx: usize,
+        old: u8,
+        new: u8,
+    },
+    /// A step over or step out reached its target.
+    StepFinished {
+        pc: u16,
+    },
+}
+
+impl fmt::Display for DebugEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugEvent::Breakpoint { pc } => write!(f, "breakpoint at {:03X}", pc),
+            DebugEvent::MemoryRead { pc, addr } => {
+                write!(f, "{:03X} read watched address {:03X}", pc, addr)
+            }
+            DebugEvent::MemoryWrite { pc, addr } => {
+                write!(f, "{:03X} wrote watched address {:03X}", pc, addr)
+            }
+            DebugEvent::RegisterChanged { pc, idx, old, new } => write!(
+                f,
+                "{:03X} changed V{:X} from {:02X} to {:02X}",
+                pc, idx, old, new
+            ),
+            DebugEvent::StepFinished { pc } => write!(f, "stopped at {:03X}", pc),
+        }
+    }
+}
+
+/// What the frontend should do once the prompt returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptAction {
+    Resume,
+    Quit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunMode {
+    Continue,
+    StepOver { return_pc: u16, depth: usize },
+    StepOut { depth: usize },
+}
+
+/// Drives a `Chip8` instruction by instruction so execution can stop on
+/// breakpoints and watchpoints.
+pub struct Debugger {
+    paused: bool,
+    mode: RunMode,
+    breakpoints: BTreeSet<u16>,
+    read_watches: BTreeSet<usize>,
+    write_watches: BTreeSet<usize>,
+    register_watches: BTreeSet<usize>,
+    // Lets execution leave the breakpoint it stopped on
+    skip_breakpoint: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            paused: false,
+            mode: RunMode::Continue,
+            breakpoints: BTreeSet::new(),
+            read_watches: BTreeSet::new(),
+            write_watches: BTreeSet::new(),
+            register_watches: BTreeSet::new(),
+            skip_breakpoint: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.mode = RunMode::Continue;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.skip_breakpoint = true;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn watch_read(&mut self, addr: usize) {
+        self.read_watches.insert(addr);
+    }
+
+    pub fn watch_write(&mut self, addr: usize) {
+        self.write_watches.insert(addr);
+    }
+
+    pub fn watch_register(&mut self, idx: usize) {
+        self.register_watches.insert(idx);
+    }
+
+    pub fn unwatch(&mut self, addr: usize) -> bool {
+        let read = self.read_watches.remove(&addr);
+        let write = self.write_watches.remove(&addr);
+        read || write
+    }
+
+    pub fn unwatch_register(&mut self, idx: usize) -> bool {
+        self.register_watches.remove(&idx)
+    }
+
+    /// Runs up to `ticks` instructions unless paused, stopping early and
+    /// pausing when a breakpoint, watchpoint or step target is hit.
+    pub fn run(
+        &mut self,
+        chip8: &mut Chip8,
+        ticks: usize,
+    ) -> Result<Option<DebugEvent>, Chip8Error> {
+        if self.paused {
+            return Ok(None);
+        }
+
+        for _ in 0..ticks {
+            // Nothing runs until the next interrupt
+            if chip8.is_stalled() {
+                break;
+            }
+            let pc = chip8.get_pc();
+            if self.breakpoints.contains(&pc) && !self.skip_breakpoint {
+                self.pause();
+                return Ok(Some(DebugEvent::Breakpoint { pc }));
+            }
+            self.skip_breakpoint = false;
+
+            if let Some(event) = self.execute(chip8)? {
+                self.pause();
+                return Ok(Some(event));
+            }
+
+            let finished = match self.mode {
+                RunMode::Continue => false,
+                RunMode::StepOver { return_pc, depth } => {
+                    chip8.get_pc() == return_pc && chip8.get_stack().len() == depth
+                }
+                RunMode::StepOut { depth } => chip8.get_stack().len() < depth,
+            };
+            if finished {
+                self.pause();
+                return Ok(Some(DebugEvent::StepFinished { pc: chip8.get_pc() }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Executes exactly one instruction, reporting any watchpoint it hit.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<Option<DebugEvent>, Chip8Error> {
+        self.execute(chip8)
+    }
+
+    /// Runs a 2NNN call to completion and stops after it returns; any other
+    /// instruction is single-stepped.
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Result<Option<DebugEvent>, Chip8Error> {
+        if chip8.peek_op()? & 0xF000 != 0x2000 {
+            return self.step(chip8);
+        }
+
+        // A call in the last word of XO-CHIP memory has nowhere to return to
+        let Some(return_pc) = chip8.get_pc().checked_add(2) else {
+            return self.step(chip8);
+        };
+        self.mode = RunMode::StepOver {
+            return_pc,
+            depth: chip8.get_stack().len(),
+        };
+        self.resume();
+        Ok(None)
+    }
+
+    /// Runs until the current subroutine returns with 00EE. Returns `false`
+    /// when not inside a subroutine.
+    pub fn step_out(&mut self, chip8: &Chip8) -> bool {
+        let depth = chip8.get_stack().len();
+        if depth == 0 {
+            return false;
+        }
+
+        self.mode = RunMode::StepOut { depth };
+        self.resume();
+        true
+    }
+
+    fn execute(&mut self, chip8: &mut Chip8) -> Result<Option<DebugEvent>, Chip8Error> {
+        let pc = chip8.get_pc();
+        let regs = chip8.get_v_regs();
+        let watch_memory = !self.read_watches.is_empty() || !self.write_watches.is_empty();
+
+        chip8.set_access_logging(watch_memory);
+        let result = chip8.tick();
+        let accesses = chip8.take_accesses();
+        chip8.set_access_logging(false);
+        result?;
+
+        for access in accesses {
+            if access.write && self.write_watches.contains(&access.addr) {
+                return Ok(Some(DebugEvent::MemoryWrite {
+                    pc,
+                    addr: access.addr,
+                }));
+            }
+            if !access.write && self.read_watches.contains(&access.addr) {
+                return Ok(Some(DebugEvent::MemoryRead {
+                    pc,
+                    addr: access.addr,
+                }));
+            }
+        }
+
+        let new_regs = chip8.get_v_regs();
+        for &idx in &self.register_watches {
+            if regs[idx] != new_regs[idx] {
+                return Ok(Some(DebugEvent::RegisterChanged {
+                    pc,
+                    idx,
+                    old: regs[idx],
+                    new: new_regs[idx],
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads commands until one resumes execution or quits.
+    pub fn prompt<R: BufRead, W: Write>(
+        &mut self,
+        chip8: &mut Chip8,
+        input: &mut R,
+        output: &mut W,
+    ) -> io::Result<PromptAction> {
+        print_location(chip8, output)?;
+        loop {
+            write!(output, "(c8db) ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(PromptAction::Quit);
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (command, args) = match words.split_first() {
+                Some((command, args)) => (*command, args),
+                None => continue,
+            };
+
+            match (command, args) {
+                ("c" | "continue", []) => {
+                    self.mode = RunMode::Continue;
+                    self.resume();
+                    return Ok(PromptAction::Resume);
+                }
+                ("s" | "step", _) => {
+                    let count = match args.first() {
+                        Some(arg) => match parse_hex(arg) {
+                            Some(count) => count,
+                            None => {
+                                writeln!(output, "invalid count: {}", arg)?;
+                                continue;
+                            }
+                        },
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        match self.step(chip8) {
+                            Ok(None) => {}
+                            Ok(Some(event)) => {
+                                writeln!(output, "{}", event)?;
+                                break;
+                            }
+                            Err(err) => {
+                                writeln!(output, "fault: {}", err)?;
+                                break;
+                            }
+                        }
+                    }
+                    print_location(chip8, output)?;
+                }
+                ("n" | "next", []) => match self.step_over(chip8) {
+                    Ok(_) if !self.paused => return Ok(PromptAction::Resume),
+                    Ok(event) => {
+                        if let Some(event) = event {
+                            writeln!(output, "{}", event)?;
+                        }
+                        print_location(chip8, output)?;
+                    }
+                    Err(err) => writeln!(output, "fault: {}", err)?,
+                },
+                ("f" | "finish", []) => {
+                    if self.step_out(chip8) {
+                        return Ok(PromptAction::Resume);
+                    }
+                    writeln!(output, "not inside a subroutine")?;
+                }
+                ("b" | "break", [addr]) => match parse_pc(addr) {
+                    Some(addr) => self.add_breakpoint(addr),
+                    None => writeln!(output, "invalid address: {}", addr)?,
+                },
+                ("d" | "delete", [addr]) => match parse_pc(addr) {
+                    Some(addr) if self.remove_breakpoint(addr) => {}
+                    Some(_) => writeln!(output, "no breakpoint at {}", addr)?,
+                    None => writeln!(output, "invalid address: {}", addr)?,
+                },
+                ("watch", [addr]) => match parse_hex(addr) {
+                    Some(addr) => self.watch_write(addr),
+                    None => writeln!(output, "invalid address: {}", addr)?,
+                },
+                ("rwatch", [addr]) => match parse_hex(addr) {
+                    Some(addr) => self.watch_read(addr),
+                    None => writeln!(output, "invalid address: {}", addr)?,
+                },
+                ("vwatch", [reg]) => match parse_register(reg) {
+                    Some(idx) => self.watch_register(idx),
+                    None => writeln!(output, "invalid register: {}", reg)?,
+                },
+                ("unwatch", [target]) => {
+                    let removed = match (parse_register(target), parse_hex(target)) {
+                        (Some(idx), _) if target.starts_with(['v', 'V']) => {
+                            self.unwatch_register(idx)
+                        }
+                        (_, Some(addr)) => self.unwatch(addr),
+                        _ => false,
+                    };
+                    if !removed {
+                        writeln!(output, "no watchpoint on {}", target)?;
+                    }
+                }
+                ("info", []) => self.print_info(output)?,
+                ("r" | "regs", []) => print_registers(chip8, output)?,
+                ("m" | "mem", [addr, ..]) if args.len() <= 2 => {
+                    let len = args.get(1).map_or(Some(0x10), |len| parse_hex(len));
+                    match (parse_hex(addr), len) {
+                        (Some(addr), Some(len)) => print_memory(chip8, addr, len, output)?,
+                        _ => writeln!(output, "usage: mem ADDR [LEN]")?,
+                    }
+                }
+                ("q" | "quit", []) => return Ok(PromptAction::Quit),
+                ("h" | "help", []) => writeln!(output, "{}", HELP)?,
+                _ => writeln!(output, "unknown command, try 'help'")?,
+            }
+        }
+    }
+
+    fn print_info<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let list = |set: &BTreeSet<usize>| {
+            set.iter()
+                .map(|addr| format!("{:03X}", addr))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let breakpoints: BTreeSet<usize> = self.breakpoints.iter().map(|&a| a as usize).collect();
+        writeln!(output, "breakpoints: {}", list(&breakpoints))?;
+        writeln!(output, "write watches: {}", list(&self.write_watches))?;
+        writeln!(output, "read watches: {}", list(&self.read_watches))?;
+        let registers: Vec<String> = self
+            .register_watches
+            .iter()
+            .map(|idx| format!("V{:X}", idx))
+            .collect();
+        writeln!(output, "register watches: {}", registers.join(" "))
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// A hex address PC can hold.
+fn parse_pc(text: &str) -> Option<u16> {
+    parse_hex(text).and_then(|addr| u16::try_from(addr).ok())
+}
+
+fn parse_register(text: &str) -> Option<usize> {
+    let digits = text.trim_start_matches(['v', 'V']);
+    match usize::from_str_radix(digits, 16) {
+        Ok(idx) if idx < 16 => Some(idx),
+        _ => None,
+    }
+}
+
+fn print_location<W: Write>(chip8: &Chip8, output: &mut W) -> io::Result<()> {
+    let pc = chip8.get_pc();
+    let op = match chip8.peek_op() {
+        Ok(op) => op,
+        Err(err) => return writeln!(output, "{:03X}: {}", pc, err),
+    };
+    let text = disasm::disassemble_at(&chip8.get_memory()[pc as usize..])
+        .map_or_else(|| "???".to_string(), |(text, _)| text);
+    writeln!(output, "{:03X}: {:04X}  {}", pc, op, text)
+}
+
+/// Writes the V registers, PC, I, timers and call stack.
+pub fn print_registers<W: Write>(chip8: &Chip8, output: &mut W) -> io::Result<()> {
+    for (idx, value) in chip8.get_v_regs().iter().enumerate() {
+        let separator = if idx % 8 == 7 { "\n" } else { " " };
+        write!(output, "V{:X}={:02X}{}", idx, value, separator)?;
+    }
+    writeln!(
+        output,
+        "PC={:03X} I={:03X} DT={:02X} ST={:02X}",
+        chip8.get_pc(),
+        chip8.get_i(),
+        chip8.get_delay_timer(),
+        chip8.get_sound_timer()
+    )?;
+    let stack: Vec<String> = chip8
+        .get_stack()
+        .iter()
+        .map(|addr| format!("{:03X}", addr))
+        .collect();
+    writeln!(output, "stack: {}", stack.join(" "))
+}
+
+fn print_memory<W: Write>(
+    chip8: &Chip8,
+    addr: usize,
+    len: usize,
+    output: &mut W,
+) -> io::Result<()> {
+    let mem = chip8.get_memory();
+    let end = addr.saturating_add(len).min(mem.len());
+    for row in (addr..end).step_by(8) {
+        let bytes: Vec<String> = mem[row..(row + 8).min(end)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        writeln!(output, "{:03X}: {}", row, bytes.join(" "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{DebugEvent, Debugger, PromptAction};
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    // 200: 6005 2208 7001 1206 A300 F055 00EE
+    const ROM: [u8; 14] = [
+        0x60, 0x05, 0x22, 0x08, 0x70, 0x01, 0x12, 0x06, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE,
+    ];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut chip8 = machine();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x20A);
+
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(event, Some(DebugEvent::Breakpoint { pc: 0x20A }));
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.run(&mut chip8, 100).unwrap(), None);
+
+        // Resuming leaves the breakpoint instead of hitting it again
+        debugger.resume();
+        debugger.run(&mut chip8, 1).unwrap();
+        assert_eq!(chip8.get_pc(), 0x20C);
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut chip8 = machine();
+        let mut debugger = Debugger::new();
+        debugger.step(&mut chip8).unwrap();
+
+        debugger.step_over(&mut chip8).unwrap();
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(event, Some(DebugEvent::StepFinished { pc: 0x204 }));
+
+        let mut chip8 = machine();
+        debugger.step(&mut chip8).unwrap();
+        debugger.step(&mut chip8).unwrap();
+        assert!(debugger.step_out(&chip8));
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(event, Some(DebugEvent::StepFinished { pc: 0x204 }));
+        assert!(!debugger.step_out(&chip8));
+
+        // A call at FFFE single-steps, running off the end of memory
+        let mut rom = [0x60, 0x00].repeat((0x10000 - 0x200) / 2);
+        let last = rom.len() - 2;
+        rom[last..].copy_from_slice(&[0x22, 0x00]);
+        let mut chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        chip8.load(&rom).unwrap();
+        while chip8.get_pc() != 0xFFFE {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(
+            debugger.step_over(&mut chip8),
+            Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 })
+        );
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut chip8 = machine();
+        let mut debugger = Debugger::new();
+        debugger.watch_write(0x300);
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(
+            event,
+            Some(DebugEvent::MemoryWrite {
+                pc: 0x20A,
+                addr: 0x300
+            })
+        );
+
+        let mut chip8 = machine();
+        let mut debugger = Debugger::new();
+        debugger.watch_register(0);
+        debugger.run(&mut chip8, 100).unwrap();
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(event, None);
+        debugger.resume();
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(
+            event,
+            Some(DebugEvent::RegisterChanged {
+                pc: 0x204,
+                idx: 0,
+                old: 5,
+                new: 6
+            })
+        );
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut chip8 = machine();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+
+        let mut input =
+            Cursor::new("s 2\nb 20A\nb 1020A\nd 10000\nm FFFFFFFFFFFFFFFF 1\nbogus\nregs\nc\n");
+        let mut output = Vec::new();
+        let action = debugger
+            .prompt(&mut chip8, &mut input, &mut output)
+            .unwrap();
+        assert_eq!(action, PromptAction::Resume);
+        assert!(!debugger.is_paused());
+        assert_eq!(chip8.get_pc(), 0x208);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("208: A300  i := 0x300"));
+        assert!(output.contains("unknown command"));
+        assert!(output.contains("invalid address: 1020A"));
+        assert!(output.contains("invalid address: 10000"));
+        assert!(output.contains("V0=05"));
+        assert!(output.contains("stack: 204"));
+
+        let event = debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(event, Some(DebugEvent::Breakpoint { pc: 0x20A }));
+
+        let mut input = Cursor::new("q\n");
+        let action = debugger
+            .prompt(&mut chip8, &mut input, &mut Vec::new())
+            .unwrap();
+        assert_eq!(action, PromptAction::Quit);
+    }
+}
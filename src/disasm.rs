@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Address the interpreter loads ROMs at.
+pub const ROM_START: u16 = 0x200;
+
+const BYTES_PER_DATA_LINE: usize = 8;
+
+/// Size in bytes of the instruction starting with `op`; XO-CHIP's
+/// `i := long NNNN` takes four.
+pub fn instruction_len(op: u16) -> usize {
+    if op == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Disassembles a single opcode into Octo-style syntax, or `None` when it is
+/// not a CHIP-8, SUPER-CHIP or XO-CHIP instruction. The operand of
+/// `i := long` lives in the following word, see `disassemble_at`.
+pub fn disassemble(op: u16) -> Option<String> {
+    format_op(op, None, &|addr| format!("0x{:03X}", addr))
+}
+
+/// Disassembles the instruction at the start of `bytes`, returning its text
+/// and length.
+pub fn disassemble_at(bytes: &[u8]) -> Option<(String, usize)> {
+    let op = read_op(bytes, 0)?;
+    let len = instruction_len(op);
+    let long = if len == 4 {
+        Some(read_op(bytes, 2)?)
+    } else {
+        None
+    };
+    let text = format_op(op, long, &|addr| format!("0x{:03X}", addr))?;
+    Some((text, len))
+}
+
+fn read_op(bytes: &[u8], offset: usize) -> Option<u16> {
+    let high = *bytes.get(offset)? as u16;
+    let low = *bytes.get(offset + 1)? as u16;
+    Some((high << 8) | low)
+}
+
+/// Formats `op`, naming 12-bit addresses through `addr_name`.
+fn format_op(op: u16, long: Option<u16>, addr_name: &dyn Fn(u16) -> String) -> Option<String> {
+    let x = (op & 0x0F00) >> 8;
+    let y = (op & 0x00F0) >> 4;
+    let n = op & 0x000F;
+    let nn = op & 0x00FF;
+    let nnn = op & 0x0FFF;
+
+    let text = match ((op & 0xF000) >> 12, x, y, n) {
+        (0, 0, 0xC, _) => format!("scroll-down {}", n),
+        (0, 0, 0xD, _) => format!("scroll-up {}", n),
+        (0, 0, 0xE, 0) => "clear".to_string(),
+        (0, 0, 0xE, 0xE) => "return".to_string(),
+        (0, 0, 0xF, 0xB) => "scroll-right".to_string(),
+        (0, 0, 0xF, 0xC) => "scroll-left".to_string(),
+        (0, 0, 0xF, 0xD) => "exit".to_string(),
+        (0, 0, 0xF, 0xE) => "lores".to_string(),
+        (0, 0, 0xF, 0xF) => "hires".to_string(),
+        (1, _, _, _) => format!("jump {}", addr_name(nnn)),
+        (2, _, _, _) => format!(":call {}", addr_name(nnn)),
+        (3, _, _, _) => format!("if v{:X} != 0x{:02X} then", x, nn),
+        (4, _, _, _) => format!("if v{:X} == 0x{:02X} then", x, nn),
+        (5, _, _, 0) => format!("if v{:X} != v{:X} then", x, y),
+        (5, _, _, 2) => format!("save v{:X} - v{:X}", x, y),
+        (5, _, _, 3) => format!("load v{:X} - v{:X}", x, y),
+        (6, _, _, _) => format!("v{:X} := 0x{:02X}", x, nn),
+        (7, _, _, _) => format!("v{:X} += 0x{:02X}", x, nn),
+        (8, _, _, 0) => format!("v{:X} := v{:X}", x, y),
+        (8, _, _, 1) => format!("v{:X} |= v{:X}", x, y),
+        (8, _, _, 2) => format!("v{:X} &= v{:X}", x, y),
+        (8, _, _, 3) => format!("v{:X} ^= v{:X}", x, y),
+        (8, _, _, 4) => format!("v{:X} += v{:X}", x, y),
+        (8, _, _, 5) => format!("v{:X} -= v{:X}", x, y),
+        (8, _, _, 6) => format!("v{:X} >>= v{:X}", x, y),
+        (8, _, _, 7) => format!("v{:X} =- v{:X}", x, y),
+        (8, _, _, 0xE) => format!("v{:X} <<= v{:X}", x, y),
+        (9, _, _, 0) => format!("if v{:X} == v{:X} then", x, y),
+        (0xA, _, _, _) => format!("i := {}", addr_name(nnn)),
+        (0xB, _, _, _) => format!("jump0 {}", addr_name(nnn)),
+        (0xC, _, _, _) => format!("v{:X} := random 0x{:02X}", x, nn),
+        (0xD, _, _, _) => format!("sprite v{:X} v{:X} {}", x, y, n),
+        (0xE, _, 9, 0xE) => format!("if v{:X} -key then", x),
+        (0xE, _, 0xA, 1) => format!("if v{:X} key then", x),
+        (0xF, 0, 0, 0) => match long {
+            Some(nnnn) => format!("i := long 0x{:04X}", nnnn),
+            None => "i := long".to_string(),
+        },
+        (0xF, _, 0, 1) => format!("plane {}", x),
+        (0xF, 0, 0, 2) => "audio".to_string(),
+        (0xF, _, 0, 7) => format!("v{:X} := delay", x),
+        (0xF, _, 0, 0xA) => format!("v{:X} := key", x),
+        (0xF, _, 1, 5) => format!("delay := v{:X}", x),
+        (0xF, _, 1, 8) => format!("buzzer := v{:X}", x),
+        (0xF, _, 1, 0xE) => format!("i += v{:X}", x),
+        (0xF, _, 2, 9) => format!("i := hex v{:X}", x),
+        (0xF, _, 3, 0) => format!("i := bighex v{:X}", x),
+        (0xF, _, 3, 3) => format!("bcd v{:X}", x),
+        (0xF, _, 3, 0xA) => format!("pitch := v{:X}", x),
+        (0xF, _, 5, 5) => format!("save v{:X}", x),
+        (0xF, _, 6, 5) => format!("load v{:X}", x),
+        (0xF, _, 7, 5) => format!("saveflags v{:X}", x),
+        (0xF, _, 8, 5) => format!("loadflags v{:X}", x),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// One line of a `Listing`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Label(String),
+    Code {
+        addr: u16,
+        bytes: Vec<u8>,
+        text: String,
+    },
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+/// Annotated disassembly of a whole ROM.
+///
+/// Code is found by following control flow from the entry point: jumps and
+/// calls continue at their target, skips at both following instructions,
+/// `return`, `exit` and `jump0` end a path. Everything never reached is
+/// treated as data. Jump and call targets, and `i :=` targets inside the ROM,
+/// get labels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+}
+
+impl Listing {
+    pub fn new(rom: &[u8]) -> Self {
+        let code = find_code(rom);
+        let targets = find_targets(rom, &code);
+
+        // Lay out instructions first so targets inside one can't get a label
+        let mut starts = BTreeMap::new();
+        let mut inside = BTreeSet::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            match code.get(&offset) {
+                Some(&len) if starts_free(&starts, offset) => {
+                    starts.insert(offset, len);
+                    inside.extend(offset + 1..offset + len);
+                    offset += len;
+                }
+                _ => offset += 1,
+            }
+        }
+
+        let mut labels = BTreeMap::new();
+        for (&offset, kind) in &targets {
+            if offset < rom.len() && !inside.contains(&offset) {
+                labels.insert(
+                    offset,
+                    format!("{}_{:03X}", kind, offset + ROM_START as usize),
+                );
+            }
+        }
+        let addr_name =
+            |addr: u16| match labels.get(&(addr as usize).wrapping_sub(ROM_START as usize)) {
+                Some(label) => label.clone(),
+                None => format!("0x{:03X}", addr),
+            };
+
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < rom.len() {
+            if let Some(label) = labels.get(&offset) {
+                lines.push(Line::Label(label.clone()));
+            }
+
+            let addr = (offset + ROM_START as usize) as u16;
+            if let Some(&len) = starts.get(&offset) {
+                let bytes = rom[offset..offset + len].to_vec();
+                let op = read_op(&bytes, 0).unwrap();
+                let long = read_op(&bytes, 2);
+                let text = format_op(op, long, &addr_name).unwrap();
+                lines.push(Line::Code { addr, bytes, text });
+                offset += len;
+                continue;
+            }
+
+            // Data runs until the next instruction, label or line break
+            let mut end = offset + 1;
+            while end < rom.len()
+                && end - offset < BYTES_PER_DATA_LINE
+                && !starts.contains_key(&end)
+                && !labels.contains_key(&end)
+            {
+                end += 1;
+            }
+            lines.push(Line::Data {
+                addr,
+                bytes: rom[offset..end].to_vec(),
+            });
+            offset = end;
+        }
+
+        Listing { lines }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Label(name) => writeln!(f, ": {}", name)?,
+                Line::Code { addr, bytes, text } => {
+                    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    writeln!(f, "    {:<27} # {:03X}: {}", text, addr, hex)?;
+                }
+                Line::Data { addr, bytes } => {
+                    let data: Vec<String> =
+                        bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    let text = format!(":byte {}", data.join(" "));
+                    writeln!(f, "    {:<27} # {:03X}", text, addr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn starts_free(starts: &BTreeMap<usize, usize>, offset: usize) -> bool {
+    match starts.range(..offset).next_back() {
+        Some((&start, &len)) => start + len <= offset,
+        None => true,
+    }
+}
+
+/// Offsets of reachable instructions and their lengths.
+fn find_code(rom: &[u8]) -> BTreeMap<usize, usize> {
+    let mut code = BTreeMap::new();
+    let mut pending = vec![0];
+
+    while let Some(offset) = pending.pop() {
+        if code.contains_key(&offset) {
+            continue;
+        }
+        let op = match read_op(rom, offset) {
+            Some(op) => op,
+            None => continue,
+        };
+        let len = instruction_len(op);
+        let long = if len == 4 {
+            read_op(rom, offset + 2)
+        } else {
+            None
+        };
+        if format_op(op, long, &|_| String::new()).is_none() || (len == 4 && long.is_none()) {
+            continue;
+        }
+        code.insert(offset, len);
+
+        let target = ((op & 0x0FFF) as usize).wrapping_sub(ROM_START as usize);
+        match op >> 12 {
+            0x0 if op == 0x00EE || op == 0x00FD => {}
+            0x1 => pending.push(target),
+            0x2 => {
+                pending.push(target);
+                pending.push(offset + len);
+            }
+            0xB => {}
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => {
+                pending.push(offset + len);
+                // The skipped instruction may itself be a long load
+                let next = read_op(rom, offset + len).map_or(2, instruction_len);
+                pending.push(offset + len + next);
+            }
+            _ => pending.push(offset + len),
+        }
+    }
+
+    code
+}
+
+/// ROM offsets referenced by reachable instructions, with the kind of label
+/// they should get.
+fn find_targets(rom: &[u8], code: &BTreeMap<usize, usize>) -> BTreeMap<usize, &'static str> {
+    let mut targets = BTreeMap::new();
+    for &offset in code.keys() {
+        let op = read_op(rom, offset).unwrap();
+        let kind = match op >> 12 {
+            0x1 | 0xB => "loc",
+            0x2 => "sub",
+            0xA => "data",
+            _ => continue,
+        };
+        let target = ((op & 0x0FFF) as usize).wrapping_sub(ROM_START as usize);
+        // Calls win over jumps, and both over data
+        let entry = targets.entry(target).or_insert(kind);
+        if kind == "sub" || (kind == "loc" && *entry == "data") {
+            *entry = kind;
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_at, Line, Listing};
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(0x7312).unwrap(), "v3 += 0x12");
+        assert_eq!(disassemble(0xA2A0).unwrap(), "i := 0x2A0");
+        assert_eq!(disassemble(0xD015).unwrap(), "sprite v0 v1 5");
+        assert_eq!(disassemble(0xB300).unwrap(), "jump0 0x300");
+        assert_eq!(disassemble(0x3A07).unwrap(), "if vA != 0x07 then");
+        assert_eq!(disassemble(0x00FF).unwrap(), "hires");
+        assert_eq!(disassemble(0x5122).unwrap(), "save v1 - v2");
+        assert_eq!(disassemble(0xFF3A).unwrap(), "pitch := vF");
+        assert_eq!(disassemble(0x5121), None);
+        assert_eq!(disassemble(0xFFFF), None);
+
+        assert_eq!(
+            disassemble_at(&[0xF0, 0x00, 0x12, 0x34]).unwrap(),
+            ("i := long 0x1234".to_string(), 4)
+        );
+        assert_eq!(disassemble_at(&[0xF0, 0x00]), None);
+    }
+
+    #[test]
+    fn test_listing() {
+        // 200: A208 2206 1204  206: 00EE  208: F0 90
+        let rom = [0xA2, 0x08, 0x22, 0x06, 0x12, 0x04, 0x00, 0xEE, 0xF0, 0x90];
+        let listing = Listing::new(&rom);
+        assert_eq!(
+            listing.lines,
+            vec![
+                Line::Code {
+                    addr: 0x200,
+                    bytes: vec![0xA2, 0x08],
+                    text: "i := data_208".to_string()
+                },
+                Line::Code {
+                    addr: 0x202,
+                    bytes: vec![0x22, 0x06],
+                    text: ":call sub_206".to_string()
+                },
+                Line::Label("loc_204".to_string()),
+                Line::Code {
+                    addr: 0x204,
+                    bytes: vec![0x12, 0x04],
+                    text: "jump loc_204".to_string()
+                },
+                Line::Label("sub_206".to_string()),
+                Line::Code {
+                    addr: 0x206,
+                    bytes: vec![0x00, 0xEE],
+                    text: "return".to_string()
+                },
+                Line::Label("data_208".to_string()),
+                Line::Data {
+                    addr: 0x208,
+                    bytes: vec![0xF0, 0x90]
+                },
+            ]
+        );
+
+        let text = listing.to_string();
+        assert!(text.contains(": sub_206\n"));
+        assert!(text.contains(":byte 0xF0 0x90"));
+    }
+
+    #[test]
+    fn test_listing_target_inside_instruction() {
+        // 200: 1201 (jumps into the middle of itself)
+        let listing = Listing::new(&[0x12, 0x01, 0xAB]);
+        assert_eq!(
+            listing.lines,
+            vec![
+                Line::Code {
+                    addr: 0x200,
+                    bytes: vec![0x12, 0x01],
+                    text: "jump 0x201".to_string()
+                },
+                Line::Data {
+                    addr: 0x202,
+                    bytes: vec![0xAB]
+                },
+            ]
+        );
+    }
+}
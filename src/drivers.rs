@@ -1,3 +1,42 @@
+#[cfg(feature = "sdl")]
 pub mod audio;
-pub mod video;
+#[cfg(feature = "sdl")]
 pub mod input;
+#[cfg(feature = "sdl")]
+pub mod video;
+
+/// Shows the emulated screen.
+pub trait Display {
+    /// Draws a `width` x `height` screen of pixel values 0..=3, one per
+    /// bitplane combination.
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize);
+}
+
+/// Plays the tone while the sound timer runs.
+pub trait Beeper {
+    fn set_playing(&mut self, playing: bool);
+
+    /// Switches to an XO-CHIP audio pattern at the given pitch, or back to
+    /// the plain tone for `None`. Backends that can't play patterns may
+    /// ignore it.
+    fn set_pattern(&mut self, _pattern: Option<&[u8; 16]>, _pitch: u8) {}
+}
+
+/// A keypad key going down or up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEdge {
+    pub key: usize,
+    pub pressed: bool,
+}
+
+/// Current state of the 16 CHIP-8 keys.
+pub trait Keypad {
+    fn is_pressed(&self, key: usize) -> bool;
+
+    /// Presses and releases since the last call, oldest first, so a tap
+    /// shorter than a frame still reaches the program. Backends that only
+    /// know the current state may return none.
+    fn take_edges(&mut self) -> Vec<KeyEdge> {
+        Vec::new()
+    }
+}
@@ -1,22 +1,31 @@
+use crate::drivers::Beeper;
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 
+const PATTERN_BITS: f32 = 128.0;
+
 pub struct SquareWave {
     pub phase_inc: f32,
     pub phase: f32,
     pub volume: f32,
+    /// XO-CHIP 1-bit pattern played instead of the square wave when set.
+    pub pattern: Option<[u8; 16]>,
 }
 
 impl AudioCallback for SquareWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+            let high = match &self.pattern {
+                // One phase cycle walks through all 128 bits of the pattern
+                Some(pattern) => {
+                    let bit = (self.phase * PATTERN_BITS) as usize;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                // Generate a square wave
+                None => self.phase <= 0.5,
             };
+            *x = if high { self.volume } else { -self.volume };
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
     }
@@ -24,6 +33,9 @@ impl AudioCallback for SquareWave {
 
 pub struct AudioDriver {
     device: AudioDevice<SquareWave>,
+    freq: i32,
+    pattern: Option<([u8; 16], u8)>,
+    muted: bool,
 }
 
 impl AudioDriver {
@@ -43,18 +55,72 @@ impl AudioDriver {
                     phase_inc: 480.0 / spec.freq as f32,
                     phase: 0.0,
                     volume: 0.25,
+                    pattern: None,
                 }
             })
             .unwrap();
+        let freq = device.spec().freq;
 
-        AudioDriver { device }
+        AudioDriver {
+            device,
+            freq,
+            pattern: None,
+            muted: false,
+        }
     }
 
     pub fn play_sound(&self) {
-        self.device.resume();
+        if !self.muted {
+            self.device.resume();
+        }
+    }
+
+    /// Silences the sound timer, including any beep already playing.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        if muted {
+            self.stop_sound();
+        }
     }
 
     pub fn stop_sound(&self) {
         self.device.pause();
     }
+
+    /// Plays an XO-CHIP audio pattern at the rate given by the pitch register,
+    /// or the plain square wave when `pattern` is `None`.
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        let pattern = pattern.map(|p| (*p, pitch));
+        if pattern == self.pattern {
+            return;
+        }
+        self.pattern = pattern;
+
+        let mut wave = self.device.lock();
+        match pattern {
+            Some((bits, pitch)) => {
+                let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+                wave.phase_inc = rate / PATTERN_BITS / self.freq as f32;
+                wave.pattern = Some(bits);
+            }
+            None => {
+                wave.phase_inc = 480.0 / self.freq as f32;
+                wave.pattern = None;
+            }
+        }
+    }
+}
+
+impl Beeper for AudioDriver {
+    fn set_playing(&mut self, playing: bool) {
+        if playing {
+            self.play_sound();
+        } else {
+            self.stop_sound();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        AudioDriver::set_pattern(self, pattern, pitch);
+    }
 }
@@ -1,50 +1,167 @@
-use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::drivers::{KeyEdge, Keypad};
+use crate::keymap::{KeyKind, Keymap};
+use sdl2::controller::{Button, GameController};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::GameControllerSubsystem;
+
+/// Something that holds a keypad key down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Source {
+    Key(Scancode),
+    /// A button on the controller with this joystick instance id.
+    Button(u32, Button),
+}
 
 pub struct InputDriver {
-    key_pressed: Option<usize>,
+    scancodes: HashMap<Scancode, usize>,
+    keycodes: HashMap<Keycode, usize>,
+    buttons: HashMap<Button, usize>,
+    // Open controllers by instance id; closed when dropped
+    controllers: HashMap<u32, GameController>,
+    // Keys and buttons that are down, with the keypad key each one holds
+    held: HashMap<Source, usize>,
+    edges: Vec<KeyEdge>,
 }
 
 impl InputDriver {
+    /*
+     *      keyboard (positions)    chip8
+     *      +---+---+---+---+       +---+---+---+---+
+     *      | 1 | 2 | 3 | 4 |       | 1 | 2 | 3 | C |
+     *      +---+---+---+---+       +---+---+---+---+
+     *      | Q | W | E | R |       | 4 | 5 | 6 | D |
+     *      +---+---+---+---+       +---+---+---+---+
+     *      | A | S | D | F |       | 7 | 8 | 9 | E |
+     *      +---+---+---+---+       +---+---+---+---+
+     *      | Z | X | C | V |       | A | 0 | B | F |
+     *      +---+---+---+---+       +---+---+---+---+
+     */
     pub fn new() -> Self {
-        InputDriver { key_pressed: None }
-    }
-
-/*
- *      keyboard                chip8
- *      +---+---+---+---+       +---+---+---+---+
- *      | 1 | 2 | 3 | 4 |       | 1 | 2 | 3 | C |
- *      +---+---+---+---+       +---+---+---+---+
- *      | Q | W | E | R |       | 4 | 5 | 6 | D |
- *      +---+---+---+---+       +---+---+---+---+
- *      | A | S | D | F |       | 7 | 8 | 9 | E |
- *      +---+---+---+---+       +---+---+---+---+
- *      | Y | X | C | V |       | A | 0 | B | F |
- *      +---+---+---+---+       +---+---+---+---+
-*/
-    pub fn poll_key(&mut self, key: Keycode) {
-        self.key_pressed = match key {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
-            Keycode::Y => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
-            _ => None,
+        Self::with_keymap(&Keymap::default()).expect("default keymap uses valid scancodes")
+    }
+
+    /// Resolves the keymap's key names, failing on the first one SDL does
+    /// not know.
+    pub fn with_keymap(keymap: &Keymap) -> Result<Self, String> {
+        let mut driver = InputDriver {
+            scancodes: HashMap::new(),
+            keycodes: HashMap::new(),
+            buttons: HashMap::new(),
+            controllers: HashMap::new(),
+            held: HashMap::new(),
+            edges: Vec::new(),
+        };
+        for binding in &keymap.bindings {
+            match binding.kind {
+                KeyKind::Scancode => {
+                    let scancode = Scancode::from_name(&binding.name)
+                        .ok_or_else(|| format!("unknown scancode {}", binding.name))?;
+                    driver.scancodes.insert(scancode, binding.key);
+                }
+                KeyKind::Keycode => {
+                    let keycode = Keycode::from_name(&binding.name)
+                        .ok_or_else(|| format!("unknown keycode {}", binding.name))?;
+                    driver.keycodes.insert(keycode, binding.key);
+                }
+                KeyKind::Button => {
+                    let button = Button::from_string(&binding.name)
+                        .ok_or_else(|| format!("unknown button {}", binding.name))?;
+                    driver.buttons.insert(button, binding.key);
+                }
+            }
+        }
+        Ok(driver)
+    }
+
+    /// The keypad key bound to a keyboard key, by position first and by
+    /// character second.
+    pub fn lookup(&self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+        scancode
+            .and_then(|scancode| self.scancodes.get(&scancode))
+            .or_else(|| keycode.and_then(|keycode| self.keycodes.get(&keycode)))
+            .copied()
+    }
+
+    /// Tracks a key going down or up, ignoring keys outside the keypad. A
+    /// keypad key bound to several keys or buttons stays down until the
+    /// last of them is released, and only then records a release edge.
+    pub fn key_event(
+        &mut self,
+        keycode: Option<Keycode>,
+        scancode: Option<Scancode>,
+        pressed: bool,
+    ) {
+        // A held key is remembered by position, which can't change before
+        // it comes back up
+        let Some(scancode) = scancode else {
+            return;
         };
+
+        if !pressed {
+            self.release(Source::Key(scancode));
+        } else if let Some(key) = self.lookup(keycode, Some(scancode)) {
+            self.press(Source::Key(scancode), key);
+        }
+    }
+
+    /// Opens the controller SDL reports as added at `index`, returning its
+    /// name. SDL reports controllers plugged in before startup this way
+    /// too.
+    pub fn add_controller(
+        &mut self,
+        subsystem: &GameControllerSubsystem,
+        index: u32,
+    ) -> Result<String, String> {
+        let controller = subsystem.open(index).map_err(|err| err.to_string())?;
+        let name = controller.name();
+        self.controllers
+            .insert(controller.instance_id(), controller);
+        Ok(name)
+    }
+
+    /// Closes an unplugged controller, releasing whatever it held down.
+    pub fn remove_controller(&mut self, id: u32) {
+        self.controllers.remove(&id);
+        let sources: Vec<_> = self
+            .held
+            .keys()
+            .filter(|source| matches!(source, Source::Button(held_id, _) if *held_id == id))
+            .copied()
+            .collect();
+        for source in sources {
+            self.release(source);
+        }
+    }
+
+    /// Tracks a button on controller `id` going down or up, like
+    /// `key_event`.
+    pub fn button_event(&mut self, id: u32, button: Button, pressed: bool) {
+        if !pressed {
+            self.release(Source::Button(id, button));
+        } else if let Some(&key) = self.buttons.get(&button) {
+            self.press(Source::Button(id, button), key);
+        }
+    }
+
+    fn press(&mut self, source: Source, key: usize) {
+        if !self.is_pressed(key) {
+            self.edges.push(KeyEdge { key, pressed: true });
+        }
+        self.held.insert(source, key);
     }
 
-    pub fn get_key_pressed(&self) -> Option<usize> {
-        self.key_pressed
+    fn release(&mut self, source: Source) {
+        if let Some(key) = self.held.remove(&source) {
+            if !self.is_pressed(key) {
+                self.edges.push(KeyEdge {
+                    key,
+                    pressed: false,
+                });
+            }
+        }
     }
 }
 
@@ -53,3 +170,13 @@ impl Default for InputDriver {
         Self::new()
     }
 }
+
+impl Keypad for InputDriver {
+    fn is_pressed(&self, key: usize) -> bool {
+        self.held.values().any(|&held| held == key)
+    }
+
+    fn take_edges(&mut self) -> Vec<KeyEdge> {
+        mem::take(&mut self.edges)
+    }
+}
@@ -1,49 +1,132 @@
 use crate::chip8::*;
-use sdl2::pixels::Color;
+use crate::drivers::Display;
+use crate::filter::FilterChain;
+use crate::palette::Palette;
+use crate::render::{viewport, Frame, Persistence, Renderer, Scaling};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
 
-const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
+/// Window pixels per CHIP-8 pixel unless the command line says otherwise.
+pub const DEFAULT_SCALE: u32 = 15;
 
 pub struct VideoDriver {
+    // One streaming texture per frame size seen, which only a few
+    // resolutions and filter settings make. SDL frees them with the canvas
+    textures: Vec<Texture>,
+    texture_creator: TextureCreator<WindowContext>,
     canvas: Canvas<Window>,
+    renderer: Renderer,
+    scaling: Scaling,
 }
 
 impl VideoDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    /// Opens a resizable window `scale` times the size of the low-res
+    /// screen, or covering the desktop when `fullscreen` is set.
+    pub fn new(sdl_context: &sdl2::Sdl, scale: u32, fullscreen: bool) -> Self {
         let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem
-            .window("CHIP8 EMULATOR", WINDOW_WIDTH, WINDOW_HEIGHT)
-            .position_centered()
-            .opengl()
+        let mut builder = video_subsystem.window(
+            "CHIP8 EMULATOR",
+            SCREEN_WIDTH as u32 * scale,
+            SCREEN_HEIGHT as u32 * scale,
+        );
+        builder.position_centered().resizable();
+        if fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = builder.build().unwrap();
+        let mut canvas = window
+            .into_canvas()
+            .software()
+            .present_vsync()
             .build()
             .unwrap();
-        let mut canvas = window.into_canvas().present_vsync().build().unwrap();
 
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
 
-        VideoDriver { canvas }
+        let texture_creator = canvas.texture_creator();
+
+        VideoDriver {
+            textures: Vec::new(),
+            texture_creator,
+            canvas,
+            renderer: Renderer::new(Palette::default(), Persistence::Off),
+            scaling: Scaling::default(),
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.renderer.set_palette(palette.clone());
+    }
+
+    pub fn set_persistence(&mut self, persistence: Persistence) {
+        self.renderer.set_persistence(persistence);
+    }
+
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.renderer.set_filters(filters);
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
     }
 
-    pub fn draw_screen(&mut self, screen: &[bool]) {
-        // Black background
+    /// Switches between a window and covering the desktop, returning
+    /// whether the window is fullscreen now.
+    pub fn toggle_fullscreen(&mut self) -> Result<bool, String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = window.fullscreen_state() == FullscreenType::Off;
+        window.set_fullscreen(if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        })?;
+        Ok(fullscreen)
+    }
+
+    /// Draws a `width` x `height` screen as large as the window allows.
+    pub fn draw_screen(&mut self, screen: &[u8], width: usize, height: usize) {
+        let frame = self.renderer.render(screen, width, height);
+        self.draw_frame(&frame, (width as u32, height as u32));
+    }
+
+    /// Shows `frame` in the place of a screen of the given size, which
+    /// filters may have enlarged it from.
+    fn draw_frame(&mut self, frame: &Frame, screen_size: (u32, u32)) {
+        let size = (frame.width as u32, frame.height as u32);
+        // Hi-res mode changes the size, which needs another texture
+        let found = self.textures.iter().position(|texture| {
+            let query = texture.query();
+            (query.width, query.height) == size
+        });
+        let idx = found.unwrap_or_else(|| {
+            let texture = self
+                .texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, size.0, size.1)
+                .unwrap();
+            self.textures.push(texture);
+            self.textures.len() - 1
+        });
+        let texture = &mut self.textures[idx];
+        let bytes: Vec<u8> = frame.pixels.iter().flatten().copied().collect();
+        texture.update(None, &bytes, frame.width * 3).unwrap();
+
+        let window_size = self.canvas.output_size().unwrap();
+        let (x, y, width, height) = viewport(screen_size, window_size, self.scaling);
         self.canvas.set_draw_color(Color::RGB(0, 0, 0));
         self.canvas.clear();
-
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-        for (idx, pixel) in screen.iter().enumerate() {
-            if *pixel {
-                let x = (idx % SCREEN_WIDTH) as u32;
-                let y = (idx / SCREEN_WIDTH) as u32;
-                let rect: Rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
-                self.canvas.fill_rect(rect).unwrap();
-            }
-        }
+        self.canvas
+            .copy(texture, None, Rect::new(x, y, width, height))
+            .unwrap();
         self.canvas.present();
     }
 }
+
+impl Display for VideoDriver {
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize) {
+        self.draw_screen(screen, width, height);
+    }
+}
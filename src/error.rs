@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    UnknownOpcode { pc: u16, op: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+    InvalidRegister { idx: usize },
+    InvalidKey { idx: usize },
+    ZeroIpf,
+    RomTooLarge { size: usize, max: usize },
+    InvalidState { reason: &'static str },
+    UnsupportedStateVersion { version: u8 },
+    InvalidMovie { reason: &'static str },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, op } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", op, pc)
+            }
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "return with an empty stack"),
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:X}", addr)
+            }
+            Chip8Error::InvalidRegister { idx } => write!(f, "invalid register V{:X}", idx),
+            Chip8Error::InvalidKey { idx } => write!(f, "invalid keypad key {:X}", idx),
+            Chip8Error::ZeroIpf => write!(f, "at least one instruction per frame is needed"),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(
+                    f,
+                    "ROM is {} bytes, at most {} bytes fit in memory",
+                    size, max
+                )
+            }
+            Chip8Error::InvalidState { reason } => write!(f, "invalid save state: {}", reason),
+            Chip8Error::UnsupportedStateVersion { version } => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            Chip8Error::InvalidMovie { reason } => write!(f, "invalid movie: {}", reason),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
@@ -0,0 +1,336 @@
+use crate::palette::Rgb;
+use crate::render::Frame;
+
+// Rows of output filters work on, whatever the emulated resolution, so
+// scanlines look the same in low-res and hi-res mode
+const FILTER_HEIGHT: usize = 256;
+
+// Brightness kept by darkened scanlines and grid lines, in percent
+const SCANLINE_LEVEL: u32 = 55;
+const GRID_LEVEL: u32 = 45;
+// How much of the blurred image bloom adds back, in percent
+const BLOOM_LEVEL: u32 = 60;
+// Brightness lost towards the corners by the vignette
+const VIGNETTE_STRENGTH: f32 = 0.25;
+// How far the corners of the picture bend inwards
+const CURVATURE: f32 = 0.06;
+
+/// A post-processing effect for a retro look, applied on the CPU to the
+/// colored frame before it is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Every other line darker, like the gaps between a CRT's scanlines.
+    Scanlines,
+    /// Dark lines between emulated pixels, like an LCD.
+    Grid,
+    /// Lit pixels glow into their surroundings.
+    Bloom,
+    /// The picture bulges like the glass of a tube, with black corners.
+    Curvature,
+    /// The picture gets darker towards the edges.
+    Vignette,
+}
+
+impl Filter {
+    /// Names accepted by `from_name`.
+    pub const NAMES: [&'static str; 5] = ["scanlines", "grid", "bloom", "curvature", "vignette"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scanlines" => Some(Filter::Scanlines),
+            "grid" => Some(Filter::Grid),
+            "bloom" => Some(Filter::Bloom),
+            "curvature" => Some(Filter::Curvature),
+            "vignette" => Some(Filter::Vignette),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Scanlines => "scanlines",
+            Filter::Grid => "grid",
+            Filter::Bloom => "bloom",
+            Filter::Curvature => "curvature",
+            Filter::Vignette => "vignette",
+        }
+    }
+
+    /// Applies the filter to a frame enlarged `scale` times.
+    fn apply(&self, frame: &mut Frame, scale: usize) {
+        match self {
+            Filter::Scanlines => scanlines(frame),
+            Filter::Grid => grid(frame, scale),
+            Filter::Bloom => bloom(frame, scale),
+            Filter::Curvature => curvature(frame),
+            Filter::Vignette => vignette(frame),
+        }
+    }
+}
+
+/// Filters run one after the other on every frame. Frames are enlarged
+/// first, so the effects have finer pixels to work with than the
+/// emulated screen.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterChain {
+    pub filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    /// The chains the filter hotkey cycles through.
+    pub fn presets() -> Vec<FilterChain> {
+        ["none", "scanlines", "grid", "crt"]
+            .iter()
+            .map(|name| FilterChain::parse(name).unwrap())
+            .collect()
+    }
+
+    /// Parses comma-separated filter names, `none` for no filters or `crt`
+    /// for scanlines, bloom, vignette and curvature.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filters = Vec::new();
+        for name in spec.split(',').map(str::trim) {
+            match name {
+                "none" => {}
+                "crt" => filters.extend([
+                    Filter::Scanlines,
+                    Filter::Bloom,
+                    Filter::Vignette,
+                    Filter::Curvature,
+                ]),
+                _ => filters.push(
+                    Filter::from_name(name).ok_or_else(|| format!("unknown filter {}", name))?,
+                ),
+            }
+        }
+        Ok(FilterChain { filters })
+    }
+
+    /// The filter names joined by commas, or `none`.
+    pub fn name(&self) -> String {
+        if self.filters.is_empty() {
+            return "none".to_string();
+        }
+        let names: Vec<_> = self.filters.iter().map(Filter::name).collect();
+        names.join(",")
+    }
+
+    /// The filtered frame. Without filters it is `frame` itself.
+    pub fn apply(&self, frame: Frame) -> Frame {
+        if self.filters.is_empty() {
+            return frame;
+        }
+        let scale = (FILTER_HEIGHT / frame.height).max(1);
+        let mut frame = enlarge(&frame, scale);
+        for filter in &self.filters {
+            filter.apply(&mut frame, scale);
+        }
+        frame
+    }
+}
+
+fn enlarge(frame: &Frame, scale: usize) -> Frame {
+    let (width, height) = (frame.width * scale, frame.height * scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &frame.pixels[(y / scale) * frame.width..][..frame.width];
+        for x in 0..width {
+            pixels.push(row[x / scale]);
+        }
+    }
+    Frame {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn dim(color: Rgb, percent: u32) -> Rgb {
+    color.map(|channel| (channel as u32 * percent / 100) as u8)
+}
+
+fn scanlines(frame: &mut Frame) {
+    for (y, row) in frame.pixels.chunks_mut(frame.width).enumerate() {
+        if y % 2 == 1 {
+            for pixel in row {
+                *pixel = dim(*pixel, SCANLINE_LEVEL);
+            }
+        }
+    }
+}
+
+fn grid(frame: &mut Frame, scale: usize) {
+    if scale < 2 {
+        return;
+    }
+    for (y, row) in frame.pixels.chunks_mut(frame.width).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                *pixel = dim(*pixel, GRID_LEVEL);
+            }
+        }
+    }
+}
+
+/// Adds a box-blurred copy of the frame, about one emulated pixel wide.
+fn bloom(frame: &mut Frame, scale: usize) {
+    let (width, height) = (frame.width, frame.height);
+    let radius = (scale / 2).max(1);
+    let size = (2 * radius + 1) as u32;
+
+    // Blur rows, then columns of the result
+    let mut rows = vec![[0u32; 3]; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 3];
+            for sx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                let pixel = frame.pixels[y * width + sx];
+                for (total, channel) in sum.iter_mut().zip(pixel) {
+                    *total += channel as u32;
+                }
+            }
+            rows[y * width + x] = sum.map(|total| total / size);
+        }
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 3];
+            for sy in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for (total, channel) in sum.iter_mut().zip(rows[sy * width + x]) {
+                    *total += channel;
+                }
+            }
+            let pixel = &mut frame.pixels[y * width + x];
+            for (channel, total) in pixel.iter_mut().zip(sum) {
+                let glow = total / size * BLOOM_LEVEL / 100;
+                *channel = (*channel as u32 + glow).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Position of a pixel's center between -1.0 and 1.0.
+fn centered(pos: usize, size: usize) -> f32 {
+    (2 * pos + 1) as f32 / size as f32 - 1.0
+}
+
+fn vignette(frame: &mut Frame) {
+    let (width, height) = (frame.width, frame.height);
+    for (y, row) in frame.pixels.chunks_mut(width).enumerate() {
+        let dy = centered(y, height);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let dx = centered(x, width);
+            let level = 1.0 - VIGNETTE_STRENGTH * (dx * dx + dy * dy) / 2.0;
+            *pixel = pixel.map(|channel| (channel as f32 * level).round() as u8);
+        }
+    }
+}
+
+/// Barrel distortion: each pixel shows the one further out along its row
+/// and column, the more so the nearer it is to a corner.
+fn curvature(frame: &mut Frame) {
+    let (width, height) = (frame.width, frame.height);
+    let source = frame.pixels.clone();
+    for y in 0..height {
+        let v = centered(y, height);
+        for x in 0..width {
+            let u = centered(x, width);
+            let su = u * (1.0 + CURVATURE * v * v);
+            let sv = v * (1.0 + CURVATURE * u * u);
+            let sx = ((su + 1.0) * width as f32 / 2.0).floor();
+            let sy = ((sv + 1.0) * height as f32 / 2.0).floor();
+            frame.pixels[y * width + x] =
+                if sx < 0.0 || sy < 0.0 || sx >= width as f32 || sy >= height as f32 {
+                    [0, 0, 0]
+                } else {
+                    source[sy as usize * width + sx as usize]
+                };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, FilterChain};
+    use crate::render::Frame;
+
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const BLACK: [u8; 3] = [0, 0, 0];
+
+    fn frame(width: usize, height: usize, lit: &[usize]) -> Frame {
+        let mut pixels = vec![BLACK; width * height];
+        for &idx in lit {
+            pixels[idx] = WHITE;
+        }
+        Frame {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn apply(spec: &str, frame: &Frame) -> Frame {
+        FilterChain::parse(spec).unwrap().apply(frame.clone())
+    }
+
+    #[test]
+    fn test_scanlines_and_grid() {
+        // 64 rows become 256, four per emulated row
+        let lit = frame(128, 64, &[0]);
+        let out = apply("scanlines", &lit);
+        assert_eq!((out.width, out.height), (512, 256));
+        assert_eq!(out.pixels[0], WHITE);
+        assert_eq!(out.pixels[512], [140; 3]);
+        assert_eq!(out.pixels[4], BLACK);
+
+        let out = apply("grid", &lit);
+        assert_eq!(out.pixels[2 * 512 + 2], WHITE);
+        assert_eq!(out.pixels[3], [114; 3]);
+        assert_eq!(out.pixels[3 * 512], [114; 3]);
+    }
+
+    #[test]
+    fn test_bloom() {
+        let out = apply("bloom", &frame(64, 32, &[64 + 1]));
+        // Low-res is enlarged 8 times, the glow reaches 4 pixels out
+        assert_eq!((out.width, out.height), (512, 256));
+        assert_eq!(out.pixels[8 * 512 + 8], WHITE);
+        assert_eq!(out.pixels[8 * 512 + 4], [9; 3]);
+        assert_eq!(out.pixels[8 * 512 + 3], BLACK);
+    }
+
+    #[test]
+    fn test_vignette_and_curvature() {
+        let all: Vec<_> = (0..64 * 32).collect();
+        let out = apply("vignette,curvature", &frame(64, 32, &all));
+        let center = 128 * 512 + 256;
+        assert_eq!(out.pixels[center], WHITE);
+        assert_eq!(out.pixels[0], BLACK);
+        assert_eq!(out.pixels[128 * 512], [223; 3]);
+
+        // Same input, same output
+        assert_eq!(out, apply("vignette,curvature", &frame(64, 32, &all)));
+    }
+
+    #[test]
+    fn test_parse() {
+        let crt = FilterChain::parse("crt").unwrap();
+        assert_eq!(crt.name(), "scanlines,bloom,vignette,curvature");
+        assert_eq!(FilterChain::parse("none").unwrap(), FilterChain::default());
+        assert_eq!(
+            FilterChain::parse("grid, vignette").unwrap().filters,
+            [Filter::Grid, Filter::Vignette]
+        );
+        assert_eq!(
+            FilterChain::parse("grid,blur").unwrap_err(),
+            "unknown filter blur"
+        );
+        for name in Filter::NAMES {
+            assert_eq!(Filter::from_name(name).unwrap().name(), name);
+        }
+
+        // No filters leave the frame alone
+        let small = frame(2, 1, &[1]);
+        assert_eq!(apply("none", &small), small);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::hash::Fnv1a;
+
+/// Gray level of each pixel value, matching the SDL frontend's palette.
+const GRAY_LEVELS: [u8; 4] = [0, 255, 170, 85];
+
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '%'];
+
+/// A key going down or up at the start of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+impl KeyEvent {
+    /// Parses `KEY@FRAME` with a hexadecimal key and decimal frame, as in
+    /// `5@120`.
+    pub fn parse(text: &str, pressed: bool) -> Option<Self> {
+        let (key, frame) = text.split_once('@')?;
+        let key = usize::from_str_radix(key, 16)
+            .ok()
+            .filter(|&key| key < 16)?;
+        let frame = frame.parse().ok()?;
+        Some(KeyEvent {
+            frame,
+            key,
+            pressed,
+        })
+    }
+}
+
+/// When a headless run stops.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Frames(u64),
+    Cycles(u64),
+}
+
+/// Runs a `Chip8` without a window: a fixed number of instructions per
+/// 60Hz frame, or fewer once VIP timing stalls, with keys driven by a script
+/// instead of a keyboard.
+pub struct Headless {
+    chip8: Chip8,
+    ipf: usize,
+    events: Vec<KeyEvent>,
+    frame: u64,
+    cycles: u64,
+}
+
+impl Headless {
+    /// Fails for an `ipf` of 0, which would never reach a cycle limit.
+    pub fn new(chip8: Chip8, ipf: usize) -> Result<Self, Chip8Error> {
+        if ipf == 0 {
+            return Err(Chip8Error::ZeroIpf);
+        }
+        Ok(Headless {
+            chip8,
+            ipf,
+            events: Vec::new(),
+            frame: 0,
+            cycles: 0,
+        })
+    }
+
+    /// Fails if an event names a key outside the keypad.
+    pub fn with_events(mut self, mut events: Vec<KeyEvent>) -> Result<Self, Chip8Error> {
+        if let Some(event) = events.iter().find(|event| event.key >= 16) {
+            return Err(Chip8Error::InvalidKey { idx: event.key });
+        }
+        events.sort_by_key(|event| event.frame);
+        self.events = events;
+        Ok(self)
+    }
+
+    /// Runs until `limit` is reached or the program exits with 00FD.
+    pub fn run(&mut self, limit: Limit) -> Result<(), Chip8Error> {
+        loop {
+            let done = match limit {
+                Limit::Frames(frames) => self.frame >= frames,
+                Limit::Cycles(cycles) => self.cycles >= cycles,
+            };
+            if done || self.chip8.is_halted() {
+                return Ok(());
+            }
+
+            for event in self.events.iter().filter(|e| e.frame == self.frame) {
+                self.chip8.keypress(event.key, event.pressed)?;
+            }
+
+            for _ in 0..self.ipf {
+                if let Limit::Cycles(cycles) = limit {
+                    if self.cycles >= cycles {
+                        return Ok(());
+                    }
+                }
+                if self.chip8.is_stalled() {
+                    break;
+                }
+                self.chip8.tick()?;
+                self.cycles += 1;
+            }
+            self.chip8.timer_tick();
+            self.frame += 1;
+        }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    /// Frames completed so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+}
+
+/// 64-bit FNV-1a hash of the screen contents and resolution, for comparing
+/// frames against known good output.
+pub fn frame_hash(screen: &[u8], width: usize, height: usize) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(&(width as u32).to_le_bytes());
+    hasher.write(&(height as u32).to_le_bytes());
+    hasher.write(screen);
+    hasher.finish()
+}
+
+/// `frame_hash` of the machine's current screen.
+pub fn screen_hash(chip8: &Chip8) -> u64 {
+    let (width, height) = chip8.get_resolution();
+    frame_hash(chip8.get_screen(), width, height)
+}
+
+/// One line of text per screen row.
+pub fn to_ascii(screen: &[u8], width: usize) -> String {
+    let mut text = String::with_capacity(screen.len() + screen.len() / width);
+    for row in screen.chunks(width) {
+        text.extend(row.iter().map(|&pixel| ASCII_PIXELS[pixel as usize & 3]));
+        text.push('\n');
+    }
+    text
+}
+
+/// Binary PBM (P4) with every lit pixel black, enlarged `scale` times.
+pub fn to_pbm(screen: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let (out_width, out_height) = (width * scale, height * scale);
+    let mut pbm = format!("P4\n{} {}\n", out_width, out_height).into_bytes();
+    for y in 0..out_height {
+        let mut row = vec![0u8; out_width.div_ceil(8)];
+        for x in 0..out_width {
+            if screen[(y / scale) * width + x / scale] != 0 {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        pbm.extend_from_slice(&row);
+    }
+    pbm
+}
+
+/// 8-bit grayscale PNG, enlarged `scale` times. The image data is stored
+/// uncompressed, so no deflate implementation is needed.
+pub fn to_png(screen: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let (out_width, out_height) = (width * scale, height * scale);
+
+    // Each row starts with filter type 0
+    let mut raw = Vec::with_capacity((out_width + 1) * out_height);
+    for y in 0..out_height {
+        raw.push(0);
+        for x in 0..out_width {
+            let pixel = screen[(y / scale) * width + x / scale];
+            raw.push(GRAY_LEVELS[pixel as usize & 3]);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(out_width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(out_height as u32).to_be_bytes());
+    // Bit depth 8, grayscale, deflate, no filtering, no interlace
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, frame_hash, to_ascii, to_pbm, to_png, Headless, KeyEvent, Limit};
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_scripted_keys() {
+        // F00A 7001 1200: wait for a key into V0, then count up
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0xF0, 0x0A, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        let events = vec![
+            KeyEvent::parse("a@4", false).unwrap(),
+            KeyEvent::parse("a@3", true).unwrap(),
+        ];
+        assert_eq!(KeyEvent::parse("10@3", true), None);
+        let outside = KeyEvent {
+            frame: 0,
+            key: 16,
+            pressed: true,
+        };
+        let headless = Headless::new(Chip8::new(Quirks::default()), 4).unwrap();
+        assert_eq!(
+            headless.with_events(vec![outside]).err(),
+            Some(Chip8Error::InvalidKey { idx: 16 })
+        );
+        assert_eq!(
+            Headless::new(Chip8::new(Quirks::default()), 0).err(),
+            Some(Chip8Error::ZeroIpf)
+        );
+
+        let mut headless = Headless::new(chip8, 4)
+            .and_then(|headless| headless.with_events(events))
+            .unwrap();
+        headless.run(Limit::Frames(4)).unwrap();
+        assert_eq!(headless.chip8().get_v_regs()[0], 0);
+        assert_eq!(headless.chip8().get_pc(), 0x200);
+
+        headless.run(Limit::Cycles(18)).unwrap();
+        assert_eq!(headless.cycles(), 18);
+        assert_eq!(headless.frame(), 4);
+        assert!(headless.chip8().get_v_regs()[0] > 0xA);
+    }
+
+    #[test]
+    fn test_fault_stops_run() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0xFF, 0xFF]).unwrap();
+        let mut headless = Headless::new(chip8, 10).unwrap();
+        assert_eq!(
+            headless.run(Limit::Frames(1)),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                op: 0xFFFF
+            })
+        );
+    }
+
+    #[test]
+    fn test_dumps() {
+        let screen = [0, 1, 1, 0, 2, 0];
+        assert_eq!(to_ascii(&screen, 3), ".##\n.+.\n");
+        assert_eq!(to_pbm(&screen, 3, 2, 1), b"P4\n3 2\n\x60\x40");
+        assert_ne!(frame_hash(&screen, 3, 2), frame_hash(&screen, 2, 3));
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+
+        let png = to_png(&screen, 3, 2, 2);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[16..24], [0, 0, 0, 6, 0, 0, 0, 4]);
+        assert!(png.ends_with(&[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+    }
+}
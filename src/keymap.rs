@@ -0,0 +1,403 @@
+use std::error::Error;
+use std::fmt;
+
+const NUM_KEYS: usize = 16;
+
+// Keypad keys in the order presets list their bindings, row by row:
+//
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+const KEYPAD_ORDER: [usize; NUM_KEYS] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// The left-hand 4x4 block of each layout, as SDL key names
+const PRESETS: [(&str, [&str; NUM_KEYS]); 4] = [
+    (
+        "qwerty",
+        [
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+        ],
+    ),
+    (
+        "qwertz",
+        [
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V",
+        ],
+    ),
+    (
+        "azerty",
+        [
+            "1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+        ],
+    ),
+    (
+        "dvorak",
+        [
+            "1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K",
+        ],
+    ),
+];
+
+// Directions on the D-pad, the usual fire key on A, and the rest of the
+// face and shoulder buttons on the remaining keys of the middle columns
+const DEFAULT_BUTTONS: [(&str, usize); 10] = [
+    ("dpup", 0x2),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("dpdown", 0x8),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0x7),
+    ("y", 0x9),
+    ("leftshoulder", 0x1),
+    ("rightshoulder", 0x3),
+];
+
+/// How a binding names its keyboard key or controller button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    /// An SDL scancode name: a physical position, whatever the layout prints
+    /// on it.
+    Scancode,
+    /// An SDL keycode name: the character the active layout types.
+    Keycode,
+    /// An SDL game controller button name, like `dpup`, `a` or
+    /// `leftshoulder`.
+    Button,
+}
+
+impl KeyKind {
+    fn is_keyboard(self) -> bool {
+        self != KeyKind::Button
+    }
+}
+
+/// One keyboard key or controller button driving one keypad key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub kind: KeyKind,
+    pub name: String,
+    pub key: usize,
+}
+
+/// Maps keyboard keys and controller buttons to the 16 keypad keys. Several
+/// of them may drive the same keypad key.
+///
+/// Keymap files are a small subset of TOML: `[scancodes]`, `[keycodes]` and
+/// `[buttons]` tables, each binding SDL key or button names to keypad keys.
+///
+/// ```text
+/// [scancodes]
+/// 1 = 0x1
+/// Q = 0x4
+///
+/// [keycodes]
+/// ";" = 0xA
+///
+/// [buttons]
+/// a = 0x6
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+}
+
+/// A syntax error in a keymap file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeymapError {}
+
+impl Default for Keymap {
+    /// The positions of 1234, QWER, ASDF and ZXCV on a US keyboard, which
+    /// are the same physical keys on any layout, and the default controller
+    /// buttons.
+    fn default() -> Self {
+        Keymap::from_buttons(DEFAULT_BUTTONS)
+            .over(Keymap::from_names(KeyKind::Scancode, &PRESETS[0].1))
+    }
+}
+
+impl Keymap {
+    /// Names accepted by `preset`.
+    pub const PRESETS: [&'static str; 4] = ["qwerty", "qwertz", "azerty", "dvorak"];
+
+    /// The same 4x4 block as the default on a keyboard with the given
+    /// layout, bound by the characters its keys type, e.g. YXCV as the
+    /// bottom row for `qwertz`. Presets bind no buttons, see `over`.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, names)| Keymap::from_names(KeyKind::Keycode, names))
+    }
+
+    /// Layers this keymap over `base`: the keyboard bindings of `base` are
+    /// kept when this keymap has none, and likewise its buttons. A per-ROM
+    /// keymap can so remap just the controller.
+    pub fn over(self, base: Keymap) -> Keymap {
+        let has_keyboard = self.bindings.iter().any(|b| b.kind.is_keyboard());
+        let has_buttons = self.bindings.iter().any(|b| !b.kind.is_keyboard());
+        let kept = base.bindings.into_iter().filter(|binding| {
+            if binding.kind.is_keyboard() {
+                !has_keyboard
+            } else {
+                !has_buttons
+            }
+        });
+        Keymap {
+            bindings: kept.chain(self.bindings).collect(),
+        }
+    }
+
+    /// Parses a keymap file. Key names are not checked against SDL here.
+    pub fn parse(text: &str) -> Result<Self, KeymapError> {
+        let mut bindings: Vec<Binding> = Vec::new();
+        let mut kind = None;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| KeymapError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let (table, rest) = header
+                    .split_once(']')
+                    .ok_or_else(|| error("expected ] after the table name".to_string()))?;
+                check_end(rest).map_err(error)?;
+                kind = Some(match table.trim() {
+                    "scancodes" => KeyKind::Scancode,
+                    "keycodes" => KeyKind::Keycode,
+                    "buttons" => KeyKind::Button,
+                    other => return Err(error(format!("unknown table [{}]", other))),
+                });
+                continue;
+            }
+
+            let kind = kind.ok_or_else(|| {
+                error("bindings must follow [scancodes], [keycodes] or [buttons]".to_string())
+            })?;
+            let (name, key) = parse_binding(line).map_err(error)?;
+            if bindings.iter().any(|b| b.kind == kind && b.name == name) {
+                return Err(error(format!("{} is bound twice", name)));
+            }
+            bindings.push(Binding { kind, name, key });
+        }
+        Ok(Keymap { bindings })
+    }
+
+    fn from_names(kind: KeyKind, names: &[&str; NUM_KEYS]) -> Self {
+        let bindings = names
+            .iter()
+            .zip(KEYPAD_ORDER)
+            .map(|(name, key)| Binding {
+                kind,
+                name: name.to_string(),
+                key,
+            })
+            .collect();
+        Keymap { bindings }
+    }
+
+    /// Binds controller buttons to keypad keys, by SDL button name.
+    pub fn from_buttons<'a>(buttons: impl IntoIterator<Item = (&'a str, usize)>) -> Self {
+        let bindings = buttons
+            .into_iter()
+            .map(|(name, key)| Binding {
+                kind: KeyKind::Button,
+                name: name.to_string(),
+                key,
+            })
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+/// Splits `NAME = KEY`, where NAME is bare or quoted and KEY a decimal or
+/// `0x` hex number below 16.
+fn parse_binding(line: &str) -> Result<(String, usize), String> {
+    let (name, rest) = match line.chars().next() {
+        Some(quote @ ('"' | '\'')) => parse_string(&line[1..], quote)?,
+        _ => {
+            let end = line.find('=').ok_or("expected NAME = KEY")?;
+            let name = line[..end].trim();
+            let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+            if name.is_empty() || !name.chars().all(bare) {
+                return Err(format!("{} needs to be quoted", name));
+            }
+            (name.to_string(), &line[end..])
+        }
+    };
+
+    let value = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or("expected = after the key name")?
+        .trim_start();
+    let end = value.find(|c: char| c.is_whitespace() || c == '#');
+    let (number, rest) = value.split_at(end.unwrap_or(value.len()));
+    check_end(rest)?;
+
+    let key = match number.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => number.parse(),
+    }
+    .map_err(|_| format!("invalid number {}", number))?;
+    if key >= NUM_KEYS {
+        return Err(format!("keypad key {} out of range", number));
+    }
+    Ok((name, key))
+}
+
+/// Reads a string up to the closing `quote`, returning it and the rest of
+/// the line. Double-quoted strings understand `\"` and `\\`.
+fn parse_string(text: &str, quote: char) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Ok((value, &text[index + 1..])),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                _ => return Err("unsupported escape sequence".to_string()),
+            },
+            _ => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Only a comment may follow a table header or binding.
+fn check_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected {}", rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Binding, KeyKind, Keymap, KeymapError};
+
+    #[test]
+    fn test_presets() {
+        let default = Keymap::default();
+        assert_eq!(default.bindings.len(), 26);
+        assert!(default.bindings[..16]
+            .iter()
+            .all(|binding| binding.kind == KeyKind::Scancode));
+        assert!(default.bindings[16..]
+            .iter()
+            .all(|binding| binding.kind == KeyKind::Button));
+
+        for name in Keymap::PRESETS {
+            let mut keys: Vec<_> = Keymap::preset(name)
+                .unwrap()
+                .bindings
+                .iter()
+                .filter(|binding| binding.kind == KeyKind::Keycode)
+                .map(|binding| binding.key)
+                .collect();
+            keys.sort();
+            assert_eq!(keys, (0..16).collect::<Vec<_>>());
+        }
+
+        let qwertz = Keymap::preset("QWERTZ").unwrap();
+        assert_eq!(
+            qwertz.bindings[12],
+            Binding {
+                kind: KeyKind::Keycode,
+                name: "Y".to_string(),
+                key: 0xA
+            }
+        );
+        assert_eq!(Keymap::preset("colemak"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let keymap = Keymap::parse(
+            "# Arrows and space\n\
+             [scancodes]\n\
+             Up = 0x2 # up\n\
+             Space = 5\n\
+             \n\
+             [keycodes]\n\
+             \";\" = 0xA\n\
+             '\\' = 0xB\n\
+             \"\\\"\" = 0xC\n",
+        )
+        .unwrap();
+        let names: Vec<_> = keymap
+            .bindings
+            .iter()
+            .map(|binding| (binding.kind, binding.name.as_str(), binding.key))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (KeyKind::Scancode, "Up", 2),
+                (KeyKind::Scancode, "Space", 5),
+                (KeyKind::Keycode, ";", 0xA),
+                (KeyKind::Keycode, "\\", 0xB),
+                (KeyKind::Keycode, "\"", 0xC),
+            ]
+        );
+
+        let error = |text: &str| Keymap::parse(text).unwrap_err();
+        assert_eq!(
+            error("Q = 1"),
+            KeymapError {
+                line: 1,
+                message: "bindings must follow [scancodes], [keycodes] or [buttons]".to_string()
+            }
+        );
+        assert_eq!(error("[keys]").message, "unknown table [keys]");
+        assert_eq!(error("[scancodes]\n\nQ = 16").line, 3);
+        assert_eq!(error("[scancodes]\nQ = 1 2").message, "unexpected 2");
+        assert_eq!(error("[scancodes]\n; = 1").message, "; needs to be quoted");
+        assert_eq!(
+            error("[scancodes]\nQ = 1\nQ = 2").message,
+            "Q is bound twice"
+        );
+    }
+
+    #[test]
+    fn test_over() {
+        let buttons = Keymap::parse("[buttons]\na = 0x6\n").unwrap();
+        let keymap = buttons.over(Keymap::default());
+        assert_eq!(keymap.bindings.len(), 17);
+        assert_eq!(keymap.bindings[15].name, "V");
+        assert_eq!(
+            keymap.bindings[16],
+            Binding {
+                kind: KeyKind::Button,
+                name: "a".to_string(),
+                key: 6
+            }
+        );
+
+        let keymap = Keymap::preset("dvorak").unwrap().over(Keymap::default());
+        assert_eq!(keymap.bindings.len(), 26);
+        assert_eq!(keymap.bindings[14].name, "'");
+        assert_eq!(keymap.bindings[14].kind, KeyKind::Keycode);
+    }
+}
@@ -1,4 +1,27 @@
+pub mod asm;
 pub mod chip8;
+pub mod config;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod filter;
+pub mod hash;
+pub mod headless;
+pub mod json;
+pub mod keymap;
+pub mod machine;
+pub mod movie;
+pub mod palette;
+pub mod platform;
+pub mod quirks;
+pub mod render;
+pub mod rewind;
+pub mod rng;
+pub mod romdb;
+pub mod scheduler;
 pub mod stack;
+pub mod state;
+pub mod timing;
+pub mod tui;
 pub mod register;
 pub mod drivers;
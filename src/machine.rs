@@ -0,0 +1,242 @@
+use crate::chip8::Chip8;
+use crate::debugger::{DebugEvent, Debugger};
+use crate::drivers::{Beeper, Display, Keypad};
+use crate::error::Chip8Error;
+
+/// Drives a `Chip8` through a set of frontend drivers: keys come from the
+/// `Keypad`, the screen goes to the `Display` and the sound timer to the
+/// `Beeper`. Timing is left to the frontend, which decides how often to run
+/// instructions, tick the timers and present.
+pub struct Machine<D: Display, B: Beeper, K: Keypad> {
+    chip8: Chip8,
+    display: D,
+    beeper: B,
+    keypad: K,
+    key_override: Option<u16>,
+}
+
+impl<D: Display, B: Beeper, K: Keypad> Machine<D, B, K> {
+    pub fn new(chip8: Chip8, display: D, beeper: B, keypad: K) -> Self {
+        Machine {
+            chip8,
+            display,
+            beeper,
+            keypad,
+            key_override: None,
+        }
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    pub fn display(&self) -> &D {
+        &self.display
+    }
+
+    pub fn display_mut(&mut self) -> &mut D {
+        &mut self.display
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut K {
+        &mut self.keypad
+    }
+
+    /// Replaces the keypad with a fixed key mask, bit N for key N, until
+    /// called again with `None`. Used to play back movies.
+    pub fn set_key_override(&mut self, keys: Option<u16>) {
+        self.key_override = keys;
+    }
+
+    /// Keys the next instructions will see, bit N set while key N is held.
+    pub fn key_mask(&self) -> u16 {
+        match self.key_override {
+            Some(keys) => keys,
+            None => (0..16)
+                .filter(|&key| self.keypad.is_pressed(key))
+                .fold(0, |mask, key| mask | 1 << key),
+        }
+    }
+
+    /// Runs `ticks` instructions with the current keypad state, stopping
+    /// early when VIP timing stalls until the next timer tick.
+    pub fn run(&mut self, ticks: usize) -> Result<(), Chip8Error> {
+        self.sync_keys()?;
+        for _ in 0..ticks {
+            if self.chip8.is_stalled() {
+                break;
+            }
+            self.chip8.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Like `run`, but through `debugger` so breakpoints and watches apply.
+    pub fn run_with(
+        &mut self,
+        debugger: &mut Debugger,
+        ticks: usize,
+    ) -> Result<Option<DebugEvent>, Chip8Error> {
+        self.sync_keys()?;
+        debugger.run(&mut self.chip8, ticks)
+    }
+
+    /// Counts the delay and sound timers down, once per 60Hz frame.
+    pub fn timer_tick(&mut self) {
+        self.chip8.timer_tick();
+    }
+
+    /// Hands the current screen and sound state to the drivers.
+    pub fn present(&mut self) {
+        self.beeper
+            .set_pattern(self.chip8.get_audio_pattern(), self.chip8.get_pitch());
+        self.beeper.set_playing(self.chip8.get_sound_timer() > 0);
+
+        let (width, height) = self.chip8.get_resolution();
+        self.display.draw(self.chip8.get_screen(), width, height);
+    }
+
+    /// One whole 60Hz frame: `ticks` instructions, a timer tick and a
+    /// present.
+    pub fn run_frame(&mut self, ticks: usize) -> Result<(), Chip8Error> {
+        self.run(ticks)?;
+        self.timer_tick();
+        self.present();
+        Ok(())
+    }
+
+    /// Hands key changes to the machine: the keypad's edges in order, then
+    /// whatever still differs from the current key mask.
+    fn sync_keys(&mut self) -> Result<(), Chip8Error> {
+        let edges = self.keypad.take_edges();
+        if self.key_override.is_none() {
+            for edge in edges {
+                self.chip8.keypress(edge.key, edge.pressed)?;
+            }
+        }
+
+        let mask = self.key_mask();
+        for key in 0..16 {
+            let pressed = mask & 1 << key != 0;
+            if self.chip8.is_key_pressed(key)? != pressed {
+                self.chip8.keypress(key, pressed)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Machine;
+    use crate::chip8::Chip8;
+    use crate::drivers::{Beeper, Display, KeyEdge, Keypad};
+    use crate::quirks::Quirks;
+    use std::mem;
+
+    #[derive(Default)]
+    struct FakeDisplay {
+        frames: usize,
+        size: (usize, usize),
+    }
+
+    impl Display for FakeDisplay {
+        fn draw(&mut self, screen: &[u8], width: usize, height: usize) {
+            assert_eq!(screen.len(), width * height);
+            self.frames += 1;
+            self.size = (width, height);
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeBeeper {
+        playing: bool,
+    }
+
+    impl Beeper for FakeBeeper {
+        fn set_playing(&mut self, playing: bool) {
+            self.playing = playing;
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeKeypad {
+        keys: [bool; 16],
+        edges: Vec<KeyEdge>,
+    }
+
+    impl Keypad for FakeKeypad {
+        fn is_pressed(&self, key: usize) -> bool {
+            self.keys[key]
+        }
+
+        fn take_edges(&mut self) -> Vec<KeyEdge> {
+            mem::take(&mut self.edges)
+        }
+    }
+
+    #[test]
+    fn test_machine_drivers() {
+        // 6005 E09E 1202 6103 F118 120A: wait for key 5, then start the buzzer
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8
+            .load(&[
+                0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x61, 0x03, 0xF1, 0x18, 0x12, 0x0A,
+            ])
+            .unwrap();
+        let mut machine = Machine::new(
+            chip8,
+            FakeDisplay::default(),
+            FakeBeeper::default(),
+            FakeKeypad::default(),
+        );
+
+        machine.run_frame(10).unwrap();
+        assert!(!machine.beeper.playing);
+        assert_eq!(machine.display.frames, 1);
+        assert_eq!(machine.display.size, (64, 32));
+
+        machine.keypad_mut().keys[5] = true;
+        machine.run_frame(10).unwrap();
+        assert!(machine.beeper.playing);
+        assert_eq!(machine.chip8().get_sound_timer(), 2);
+        assert_eq!(machine.display.frames, 2);
+        assert_eq!(machine.key_mask(), 1 << 5);
+
+        machine.set_key_override(Some(1 << 2));
+        assert_eq!(machine.key_mask(), 1 << 2);
+    }
+
+    #[test]
+    fn test_tap_within_frame() {
+        // F00A 1202: a key pressed and released between two frames still
+        // ends the wait
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0xF0, 0x0A, 0x12, 0x02]).unwrap();
+        let mut machine = Machine::new(
+            chip8,
+            FakeDisplay::default(),
+            FakeBeeper::default(),
+            FakeKeypad::default(),
+        );
+
+        machine.run_frame(10).unwrap();
+        machine.keypad_mut().edges = vec![
+            KeyEdge {
+                key: 7,
+                pressed: true,
+            },
+            KeyEdge {
+                key: 7,
+                pressed: false,
+            },
+        ];
+        machine.run_frame(10).unwrap();
+        assert_eq!(machine.chip8().get_v_regs()[0], 7);
+        assert_eq!(machine.chip8().get_pc(), 0x202);
+    }
+}
@@ -1,41 +1,588 @@
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Instant;
 
 use chip8::chip8::*;
+use chip8::debugger::DebugEvent;
+use chip8::debugger::{Debugger, PromptAction};
 use chip8::drivers::audio::AudioDriver;
-
 use chip8::drivers::input::InputDriver;
-use chip8::drivers::video::VideoDriver;
+use chip8::drivers::video::{VideoDriver, DEFAULT_SCALE};
+use chip8::error::Chip8Error;
+use chip8::filter::FilterChain;
+use chip8::headless::{screen_hash, Headless, Limit};
+use chip8::keymap::Keymap;
+use chip8::machine::Machine;
+use chip8::movie::{Movie, MAX_IPF};
+use chip8::palette::{load_user_palettes, Palette};
+use chip8::platform::Platform;
+use chip8::quirks::Quirks;
+use chip8::render::{Persistence, Scaling};
+use chip8::rewind::Rewind;
+use chip8::romdb::{rom_settings, RomDb, RomInfo};
+use chip8::scheduler::Scheduler;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+
+const REWIND_SECONDS: usize = 30;
+
+/// How long `--headless` runs without a movie, like `chip8-headless`.
+const HEADLESS_FRAMES: u64 = 600;
+
+/// F1-F4 load save slots 1-4, holding shift saves to them instead.
+fn save_slot(key: Keycode) -> Option<usize> {
+    match key {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Save states live next to the ROM as `game.ch8.state1` and so on.
+fn state_path(rom: &str, slot: usize) -> PathBuf {
+    PathBuf::from(format!("{}.state{}", rom, slot))
+}
+
+fn save_to_slot(chip8_emu: &Chip8, rom: &str, slot: usize) {
+    let path = state_path(rom, slot);
+    match fs::write(&path, chip8_emu.save_state()) {
+        Ok(()) => println!("Saved slot {} to {}", slot, path.display()),
+        Err(err) => eprintln!("Unable to save slot {}: {}", slot, err),
+    }
+}
+
+fn load_from_slot(chip8_emu: &mut Chip8, rom: &str, slot: usize) -> bool {
+    let path = state_path(rom, slot);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("Unable to load slot {}: {}", slot, err);
+            return false;
+        }
+    };
+    match chip8_emu.load_state(&data) {
+        Ok(()) => {
+            println!("Loaded slot {} from {}", slot, path.display());
+            true
+        }
+        Err(err) => {
+            eprintln!("Unable to load slot {}: {}", slot, err);
+            false
+        }
+    }
+}
+
+const USAGE: &str = "Usage: chip8 [options] path/to/game
+
+Options:
+  --scale N           window pixels per CHIP-8 pixel (default 15)
+  --fullscreen        cover the whole desktop instead
+  --scaling MODE      fit (keep the aspect ratio, default) or integer (whole
+                      multiples of the screen only) when resized
+  --palette NAME|COLORS
+                      default, octo, green, amber, gameboy, a palette from
+                      palettes.toml, or up to 16 hex colors for the
+                      background, plane 1, plane 2, ..., e.g. 000000,33ff66
+  --filter NAMES      post-processing, comma-separated: scanlines, grid,
+                      bloom, curvature, vignette, crt (all but grid) or
+                      none (default)
+  --persistence MODE  keep erased pixels on screen against flicker: off, or
+                      (the last two frames combined) or fade[:PERCENT]
+                      (fade out by PERCENT per frame, default 35)
+  --platform NAME     chip8, schip or xochip (default from the ROM database
+                      or the file extension)
+  --quirks NAME       vip, schip, xochip or none (default from the platform)
+  --ipf N             instructions per frame, 1 to 65535 (default from the
+                      ROM database or the platform)
+  --vip-timing        charge instructions their COSMAC VIP cycles instead
+                      of a fixed IPF
+  --seed N            seed for the random generator (default random)
+  --keymap NAME|FILE  qwerty, qwertz, azerty, dvorak or a TOML keymap
+  --mute              no sound
+  --paused            start in the debugger prompt
+  --record FILE       record the keys of every frame to a movie
+  --play FILE         play back a recorded movie
+  --headless          run 600 frames without a window and print the frame
+                      hash, see chip8-headless for more
+  --help              show this help";
+
+struct Options {
+    rom: String,
+    scale: u32,
+    fullscreen: bool,
+    scaling: Scaling,
+    palette: Option<String>,
+    persistence: Persistence,
+    filters: FilterChain,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    ipf: Option<usize>,
+    vip_timing: bool,
+    seed: Option<u64>,
+    keymap: Option<String>,
+    mute: bool,
+    paused: bool,
+    record: Option<String>,
+    play: Option<String>,
+    headless: bool,
+    help: bool,
+}
 
-const TICKS_PER_LOOP: usize = 10;
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        scale: DEFAULT_SCALE,
+        fullscreen: false,
+        scaling: Scaling::default(),
+        palette: None,
+        persistence: Persistence::Off,
+        filters: FilterChain::default(),
+        platform: None,
+        quirks: None,
+        ipf: None,
+        vip_timing: false,
+        seed: None,
+        keymap: None,
+        mute: false,
+        paused: false,
+        record: None,
+        play: None,
+        headless: false,
+        help: false,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if !options.rom.is_empty() {
+                return Err(format!("unexpected argument {}", arg));
+            }
+            options.rom = arg.clone();
+            continue;
+        }
+        let flag = match arg.as_str() {
+            "--fullscreen" => &mut options.fullscreen,
+            "--vip-timing" => &mut options.vip_timing,
+            "--mute" => &mut options.mute,
+            "--paused" => &mut options.paused,
+            "--headless" => &mut options.headless,
+            "--help" => &mut options.help,
+            _ => {
+                parse_option(&mut options, arg, iter.next())?;
+                continue;
+            }
+        };
+        *flag = true;
+    }
+
+    if options.help {
+        return Ok(options);
+    }
+    if options.rom.is_empty() {
+        return Err("no ROM given".to_string());
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    if options.headless && options.record.is_some() {
+        return Err("--record needs a window to take keys from".to_string());
+    }
+    Ok(options)
+}
+
+/// Handles an option that takes a value, the next argument.
+fn parse_option(options: &mut Options, arg: &str, value: Option<&String>) -> Result<(), String> {
+    let value = || value.ok_or_else(|| format!("{} needs a value", arg));
+    let number = || {
+        let value = value()?;
+        value
+            .parse::<u64>()
+            .map_err(|_| format!("{}: invalid number {}", arg, value))
+    };
+    match arg {
+        "--scale" => {
+            options.scale = match number()? {
+                scale @ 1..=64 => scale as u32,
+                scale => return Err(format!("{}: expected 1 to 64, got {}", arg, scale)),
+            }
+        }
+        "--ipf" => {
+            options.ipf = match number()? {
+                ipf if (1..=MAX_IPF as u64).contains(&ipf) => Some(ipf as usize),
+                ipf => return Err(format!("{}: expected 1 to {}, got {}", arg, MAX_IPF, ipf)),
+            }
+        }
+        "--seed" => options.seed = Some(number()?),
+        "--scaling" => {
+            let value = value()?;
+            options.scaling = Scaling::from_name(value)
+                .ok_or_else(|| format!("{}: unknown scaling mode {}", arg, value))?;
+        }
+        "--palette" => options.palette = Some(value()?.clone()),
+        "--filter" => {
+            options.filters =
+                FilterChain::parse(value()?).map_err(|err| format!("{}: {}", arg, err))?
+        }
+        "--persistence" => {
+            options.persistence =
+                Persistence::parse(value()?).map_err(|err| format!("{}: {}", arg, err))?
+        }
+        "--platform" => {
+            let value = value()?;
+            let platform = Platform::from_name(value)
+                .ok_or_else(|| format!("{}: unknown platform {}", arg, value))?;
+            options.platform = Some(platform);
+        }
+        "--quirks" => {
+            let value = value()?;
+            let quirks = Quirks::from_name(value)
+                .ok_or_else(|| format!("{}: unknown quirks {}", arg, value))?;
+            options.quirks = Some(quirks);
+        }
+        "--keymap" => options.keymap = Some(value()?.clone()),
+        "--record" => options.record = Some(value()?.clone()),
+        "--play" => options.play = Some(value()?.clone()),
+        _ => return Err(format!("unknown option {}", arg)),
+    }
+    Ok(())
+}
+
+/// A movie being recorded or played back.
+enum MovieSession {
+    Recording { movie: Movie, path: String },
+    Playing { movie: Movie, frame: usize },
+}
+
+impl MovieSession {
+    /// Keys for the next frame: the live ones while recording, the recorded
+    /// ones while playing. `None` once playback runs out of frames.
+    fn next_frame(&mut self, live_keys: u16) -> Option<u16> {
+        match self {
+            MovieSession::Recording { movie, .. } => {
+                movie.frames.push(live_keys);
+                Some(live_keys)
+            }
+            MovieSession::Playing { movie, frame } => {
+                let keys = movie.frames.get(*frame).copied();
+                *frame += 1;
+                keys
+            }
+        }
+    }
+}
+
+/// Prints what stopped a run, returning the fault if there was one.
+fn check_run(result: Result<Option<DebugEvent>, Chip8Error>) -> Option<Chip8Error> {
+    match result {
+        Ok(Some(event)) => {
+            println!("{}", event);
+            None
+        }
+        Ok(None) => None,
+        Err(err) => {
+            eprintln!("Emulation halted: {}", err);
+            Some(err)
+        }
+    }
+}
+
+fn load_movie(path: &str, rom: &[u8]) -> Result<(Movie, Chip8), Box<dyn Error>> {
+    let movie = Movie::from_bytes(&fs::read(path)?)?;
+    let chip8 = movie.machine(rom)?;
+    Ok((movie, chip8))
+}
+
+/// Per-ROM bindings, e.g. `path/to/game.ch8.keymap.toml`.
+fn rom_keymap_path(rom: &str) -> PathBuf {
+    PathBuf::from(format!("{}.keymap.toml", rom))
+}
+
+/// Reads a keymap file. Tables it leaves out keep the bindings of `base`.
+fn read_keymap(path: &Path, base: Keymap) -> Result<Keymap, String> {
+    let name = path.display();
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", name, err))?;
+    let keymap = Keymap::parse(&text).map_err(|err| format!("{}:{}", name, err))?;
+    Ok(keymap.over(base))
+}
+
+/// The `--keymap` preset or file over `base`, and the ROM's own keymap on
+/// top of that.
+fn load_keymap(spec: Option<&str>, rom: &str, base: Keymap) -> Result<Keymap, String> {
+    let mut keymap = match spec {
+        Some(spec) => match Keymap::preset(spec) {
+            Some(preset) => preset.over(base),
+            None => read_keymap(Path::new(spec), base)?,
+        },
+        None => base,
+    };
+    let rom_keymap = rom_keymap_path(rom);
+    if rom_keymap.exists() {
+        keymap = read_keymap(&rom_keymap, keymap)?;
+    }
+    Ok(keymap)
+}
+
+/// The palettes F9 cycles through and the index of the first one to show.
+/// The presets and the user's palettes are always there; `--palette` colors
+/// or the ROM's own from the database come first if given.
+fn palette_list(
+    spec: Option<&str>,
+    info: Option<&RomInfo>,
+) -> Result<(Vec<(String, Palette)>, usize), String> {
+    let mut palettes: Vec<_> = Palette::PRESETS
+        .iter()
+        .map(|name| (name.to_string(), Palette::preset(name).unwrap()))
+        .collect();
+    palettes.extend(load_user_palettes()?);
+
+    let custom = match spec {
+        Some(spec) => {
+            let named = palettes
+                .iter()
+                .position(|(name, _)| name.eq_ignore_ascii_case(spec));
+            if let Some(idx) = named {
+                return Ok((palettes, idx));
+            }
+            match Palette::parse(spec) {
+                Ok(palette) => Some(("custom".to_string(), palette)),
+                Err(_) if !spec.contains(',') => {
+                    return Err(format!("--palette: unknown palette {}", spec))
+                }
+                Err(err) => return Err(format!("--palette: {}", err)),
+            }
+        }
+        None => info.and_then(|info| Some((info.title.clone(), info.palette.clone()?))),
+    };
+    if let Some(custom) = custom {
+        palettes.insert(0, custom);
+    }
+    Ok((palettes, 0))
+}
+
+/// Reports whether playback ended on the frame it was recorded with.
+fn finish_playback(session: &MovieSession, chip8: &Chip8) {
+    if let MovieSession::Playing { movie, .. } = session {
+        let hash = screen_hash(chip8);
+        match movie.final_hash {
+            Some(expected) if expected != hash => eprintln!(
+                "Movie finished, but frame hash {:016x} does not match the recorded {:016x}",
+                hash, expected
+            ),
+            _ => println!("Movie finished after {} frames", movie.len()),
+        }
+    }
+}
+
+/// Runs without a window like `chip8-headless`: for the length of the
+/// movie if one is playing, otherwise for `HEADLESS_FRAMES`. Returns false
+/// when the program faults or the movie ends on a different frame.
+fn run_headless(chip8: Chip8, ipf: usize, movie: Option<&Movie>) -> bool {
+    let (events, frames) = match movie {
+        Some(movie) => (movie.key_events(), movie.len() as u64),
+        None => (Vec::new(), HEADLESS_FRAMES),
+    };
+    let mut headless = match Headless::new(chip8, ipf).and_then(|h| h.with_events(events)) {
+        Ok(headless) => headless,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    let result = headless.run(Limit::Frames(frames));
+    let hash = screen_hash(headless.chip8());
+    println!(
+        "frames: {} cycles: {} hash: {:016x}",
+        headless.frame(),
+        headless.cycles(),
+        hash
+    );
+
+    if let Err(err) = result {
+        eprintln!("Emulation halted: {}", err);
+        return false;
+    }
+    match movie.and_then(|movie| movie.final_hash) {
+        Some(expected) if expected != hash => {
+            eprintln!("frame hash does not match the recorded {:016x}", expected);
+            false
+        }
+        _ => true,
+    }
+}
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} path/to/game", &args[0]);
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
         return;
     }
+    let rom = &options.rom;
+
+    let buffer = match fs::read(rom) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            eprintln!("{}: {}", rom, err);
+            process::exit(1);
+        }
+    };
+
+    // Known ROMs come with their platform, quirks, speed and controls
+    let romdb = match RomDb::load() {
+        Ok(romdb) => romdb,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let info = romdb.lookup(&buffer);
+    if let Some(info) = info {
+        println!("Found {} in the ROM database", info.title);
+    }
+
+    // Unknown ROMs fall back to Octo-style extensions for the platform
+    let (platform, quirks, mut ipf) =
+        rom_settings(info, options.platform, options.ipf, Path::new(rom));
+    let mut chip8_emu = Chip8::new(options.quirks.unwrap_or(quirks))
+        .with_platform(platform)
+        .with_vip_timing(options.vip_timing);
+    // A fixed seed makes CXNN, and with it the whole run, repeatable
+    if let Some(seed) = options.seed {
+        chip8_emu = chip8_emu.with_seed(seed);
+    }
+
+    if let Err(err) = chip8_emu.load(&buffer) {
+        eprintln!("{}: {}", rom, err);
+        process::exit(1);
+    }
+
+    // A played movie brings its own platform, quirks, seed and timing
+    let mut movie = None;
+    if let Some(path) = &options.play {
+        match load_movie(path, &buffer) {
+            Ok((loaded, chip8)) => {
+                println!("Playing {} ({} frames)", path, loaded.len());
+                chip8_emu = chip8;
+                ipf = loaded.ipf as usize;
+                movie = Some(MovieSession::Playing {
+                    movie: loaded,
+                    frame: 0,
+                });
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut scheduler = Scheduler::for_chip8(&chip8_emu, ipf);
+
+    if options.headless {
+        let movie = match &movie {
+            Some(MovieSession::Playing { movie, .. }) => Some(movie),
+            _ => None,
+        };
+        if !run_headless(chip8_emu, scheduler.ipf(), movie) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &options.record {
+        let Ok(ipf) = u16::try_from(scheduler.ipf()) else {
+            eprintln!(
+                "Movies record at most {} instructions per frame, not {}",
+                MAX_IPF,
+                scheduler.ipf()
+            );
+            process::exit(1);
+        };
+        let recording = Movie::new(&buffer, &chip8_emu, ipf);
+        println!("Recording to {} with seed {}", path, chip8_emu.seed());
+        movie = Some(MovieSession::Recording {
+            movie: recording,
+            path: path.clone(),
+        });
+    }
 
     let sdl_context = sdl2::init().unwrap();
 
-    let mut video_driver = VideoDriver::new(&sdl_context);
-    let audio_driver = AudioDriver::new(&sdl_context);
-    let mut input_driver = InputDriver::new();
+    let mut video_driver = VideoDriver::new(&sdl_context, options.scale, options.fullscreen);
+    let (palettes, mut palette) = match palette_list(options.palette.as_deref(), info) {
+        Ok(list) => list,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    video_driver.set_palette(&palettes[palette].1);
+    video_driver.set_persistence(options.persistence);
+    video_driver.set_scaling(options.scaling);
+    // F10 goes through the presets, starting from the chosen filters
+    let mut filter_chains = FilterChain::presets();
+    let mut filters = match filter_chains.iter().position(|c| *c == options.filters) {
+        Some(idx) => idx,
+        None => {
+            filter_chains.insert(0, options.filters.clone());
+            0
+        }
+    };
+    video_driver.set_filters(filter_chains[filters].clone());
+    let mut audio_driver = AudioDriver::new(&sdl_context);
+    audio_driver.set_muted(options.mute);
+    // Controllers plugged in now or later show up as events
+    let game_controller = sdl_context.game_controller().unwrap();
+
+    let base = match info.and_then(|info| info.keymap.clone()) {
+        Some(buttons) => buttons.over(Keymap::default()),
+        None => Keymap::default(),
+    };
+    let keymap = load_keymap(options.keymap.as_deref(), rom, base);
+    let input_driver = match keymap.and_then(|keymap| InputDriver::with_keymap(&keymap)) {
+        Ok(driver) => driver,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut chip8_emu = Chip8::new();
+    let mut machine = Machine::new(chip8_emu, video_driver, audio_driver, input_driver);
+
+    // Once the core faults it stays halted, but the window keeps running
+    let mut fault: Option<Chip8Error> = None;
 
-    let mut program = File::open(&args[1]).expect("Unable to open file.");
-    let mut buffer: Vec<u8> = Vec::new();
-    program.read_to_end(&mut buffer).unwrap();
-    chip8_emu.load(&buffer);
+    // Holding backspace runs time backwards
+    let mut rewind = Rewind::new(REWIND_SECONDS);
+    let mut rewinding = false;
+
+    // F12 or a breakpoint drops into the debugger prompt on stdin. Movies
+    // log whole frames, so the debugger is off while one runs
+    let mut debugger = Debugger::new();
+    if options.paused {
+        if movie.is_some() {
+            eprintln!("The debugger can't be used during a movie");
+        } else {
+            debugger.pause();
+        }
+    }
 
-    let mut timer = Instant::now();
+    let mut last_frame = Instant::now();
     'gameloop: loop {
         for event in event_pump.poll_iter() {
             match event {
@@ -46,53 +593,171 @@ fn main() {
                 } => break 'gameloop,
 
                 Event::KeyDown {
-                    keycode: Some(key), ..
+                    keycode: Some(Keycode::F12),
+                    ..
                 } => {
-                    input_driver.poll_key(key);
-                    if let Some(k) = input_driver.get_key_pressed() {
-                        chip8_emu.keypress(k, true);
+                    if movie.is_some() {
+                        eprintln!("The debugger can't be used during a movie");
+                    } else {
+                        debugger.pause();
                     }
-                    //if let Some(k) = keyboard_to_chip8(key) {
-                    //    chip8_emu.keypress(k, true);
-                    //}
                 }
 
-                Event::KeyUp {
-                    keycode: Some(key), ..
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => {
+                    filters = (filters + 1) % filter_chains.len();
+                    let chain = &filter_chains[filters];
+                    machine.display_mut().set_filters(chain.clone());
+                    println!("Filters: {}", chain.name());
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
                 } => {
-                    input_driver.poll_key(key);
-                    if let Some(k) = input_driver.get_key_pressed() {
-                        chip8_emu.keypress(k, false);
+                    if let Err(err) = machine.display_mut().toggle_fullscreen() {
+                        eprintln!("Unable to switch fullscreen: {}", err);
                     }
-                    // if let Some(k) = keyboard_to_chip8(key) {
-                    //     chip8_emu.keypress(k, false);
-                    // }
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    palette = (palette + 1) % palettes.len();
+                    let (name, colors) = &palettes[palette];
+                    machine.display_mut().set_palette(colors);
+                    println!("Palette: {}", name);
+                }
+
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = movie.is_none(),
+
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if save_slot(key).is_some() => {
+                    let slot = save_slot(key).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        save_to_slot(machine.chip8(), rom, slot);
+                    } else if movie.is_some() {
+                        eprintln!("Save states can't be loaded during a movie");
+                    } else if load_from_slot(machine.chip8_mut(), rom, slot) {
+                        fault = None;
+                        rewind.clear();
+                    }
+                }
+
+                Event::KeyDown {
+                    keycode, scancode, ..
+                } => machine.keypad_mut().key_event(keycode, scancode, true),
+
+                Event::KeyUp {
+                    keycode, scancode, ..
+                } => machine.keypad_mut().key_event(keycode, scancode, false),
+
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match machine.keypad_mut().add_controller(&game_controller, which) {
+                        Ok(name) => println!("Controller connected: {}", name),
+                        Err(err) => eprintln!("Can't open controller: {}", err),
+                    }
+                }
+
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    machine.keypad_mut().remove_controller(which)
+                }
+
+                Event::ControllerButtonDown { which, button, .. } => {
+                    machine.keypad_mut().button_event(which, button, true)
+                }
+
+                Event::ControllerButtonUp { which, button, .. } => {
+                    machine.keypad_mut().button_event(which, button, false)
                 }
 
                 _ => {}
             }
         }
 
-        for _ in 0..TICKS_PER_LOOP {
-            chip8_emu.tick();
+        // Run every emulated frame that is due, however often this loop runs
+        let now = Instant::now();
+        let frames = scheduler.advance(now - last_frame);
+        last_frame = now;
+        for _ in 0..frames {
+            if debugger.is_paused() {
+                break;
+            }
+            if rewinding {
+                match rewind.rewind(machine.chip8_mut()) {
+                    Ok(true) => fault = None,
+                    Ok(false) => {}
+                    Err(err) => eprintln!("Unable to rewind: {}", err),
+                }
+                continue;
+            }
+            if fault.is_some() {
+                continue;
+            }
+
+            if let Some(session) = movie.as_mut() {
+                machine.set_key_override(None);
+                match session.next_frame(machine.key_mask()) {
+                    Some(keys) => machine.set_key_override(Some(keys)),
+                    None => {
+                        finish_playback(session, machine.chip8());
+                        movie = None;
+                    }
+                }
+            }
+            // A breakpoint would end the frame early, unlike on replay
+            fault = if movie.is_some() {
+                check_run(machine.run(scheduler.ipf()).map(|()| None))
+            } else {
+                check_run(machine.run_with(&mut debugger, scheduler.ipf()))
+            };
+            machine.timer_tick();
+            rewind.record(machine.chip8());
         }
 
-        // Timer tick every 60Hz
-        if timer.elapsed() >= Duration::from_secs_f32(0.01667) {
-            chip8_emu.timer_tick();
-            timer = Instant::now();
+        let mut redraw = frames > 0;
+        if debugger.is_paused() {
+            let stdin = io::stdin();
+            match debugger.prompt(machine.chip8_mut(), &mut stdin.lock(), &mut io::stdout()) {
+                Ok(PromptAction::Resume) => {}
+                Ok(PromptAction::Quit) | Err(_) => break 'gameloop,
+            }
+            // Time spent at the prompt doesn't count towards emulated time
+            scheduler.reset();
+            last_frame = Instant::now();
+            redraw = true;
         }
 
-        let sound_timer = chip8_emu.get_sound_timer();
-        if sound_timer > 0 {
-            // play sound
-            audio_driver.play_sound();
+        if redraw {
+            machine.present();
         } else {
-            // stop sound
-            audio_driver.stop_sound();
+            thread::sleep(scheduler.until_next_frame());
         }
+    }
 
-        let screen = chip8_emu.get_screen();
-        video_driver.draw_screen(screen);
+    if let Some(MovieSession::Recording { mut movie, path }) = movie {
+        movie.final_hash = Some(screen_hash(machine.chip8()));
+        match fs::write(&path, movie.to_bytes()) {
+            Ok(()) => println!("Saved {} frames to {}", movie.len(), path),
+            Err(err) => eprintln!("Unable to save movie: {}", err),
+        }
     }
 }
@@ -0,0 +1,242 @@
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::hash::fnv1a;
+use crate::headless::{Headless, KeyEvent};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::state::{StateReader, StateWriter};
+
+const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+
+const MOVIE_VERSION: u8 = 2;
+
+// Version 1 movies lack the VIP timing flag and are still accepted
+const OLDEST_MOVIE_VERSION: u8 = 1;
+
+const NUM_KEYS: usize = 16;
+
+/// Most instructions per frame a movie can record.
+pub const MAX_IPF: usize = u16::MAX as usize;
+
+/// A recorded play session: the keypad state at the start of every 60Hz
+/// frame, plus everything else needed to replay it bit for bit.
+///
+/// Every frame applies its keys, runs `ipf` instructions, or fewer with VIP
+/// timing, and ticks the timers once, the same way `Headless` runs a ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub ipf: u16,
+    pub vip_timing: bool,
+    /// One bit per key, bit N set while key N is held.
+    pub frames: Vec<u16>,
+    /// Frame hash at the end of recording, see `headless::frame_hash`.
+    pub final_hash: Option<u64>,
+}
+
+impl Movie {
+    /// Starts an empty recording of `rom` running on `chip8`.
+    pub fn new(rom: &[u8], chip8: &Chip8, ipf: u16) -> Self {
+        Movie {
+            rom_hash: fnv1a(rom),
+            seed: chip8.seed(),
+            platform: chip8.platform(),
+            quirks: chip8.quirks(),
+            ipf,
+            vip_timing: chip8.vip_timing(),
+            frames: Vec::new(),
+            final_hash: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Builds the machine the movie was recorded on, with `rom` loaded.
+    pub fn machine(&self, rom: &[u8]) -> Result<Chip8, Chip8Error> {
+        if fnv1a(rom) != self.rom_hash {
+            return Err(Chip8Error::InvalidMovie {
+                reason: "recorded with a different ROM",
+            });
+        }
+        let mut chip8 = Chip8::new(self.quirks)
+            .with_platform(self.platform)
+            .with_seed(self.seed)
+            .with_vip_timing(self.vip_timing);
+        chip8.load(rom)?;
+        Ok(chip8)
+    }
+
+    /// The frames as key presses and releases.
+    pub fn key_events(&self) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        let mut previous = 0;
+        for (frame, &keys) in self.frames.iter().enumerate() {
+            for key in 0..NUM_KEYS {
+                let bit = 1 << key;
+                if (keys ^ previous) & bit != 0 {
+                    events.push(KeyEvent {
+                        frame: frame as u64,
+                        key,
+                        pressed: keys & bit != 0,
+                    });
+                }
+            }
+            previous = keys;
+        }
+        events
+    }
+
+    /// Sets up a headless replay of the movie. Running it for `len()` frames
+    /// reproduces the recorded session.
+    pub fn playback(&self, rom: &[u8]) -> Result<Headless, Chip8Error> {
+        let chip8 = self.machine(rom)?;
+        Headless::new(chip8, self.ipf as usize)?.with_events(self.key_events())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(MOVIE_MAGIC);
+        writer.write_u8(MOVIE_VERSION);
+        writer.write_u64(self.rom_hash);
+        writer.write_u64(self.seed);
+        writer.write_u8(self.platform.id());
+        writer.write_u8(self.quirks.to_bits());
+        writer.write_u16(self.ipf);
+        writer.write_bool(self.vip_timing);
+        writer.write_bool(self.final_hash.is_some());
+        writer.write_u64(self.final_hash.unwrap_or_default());
+        writer.write_u32(self.frames.len() as u32);
+        for &keys in &self.frames {
+            writer.write_u16(keys);
+        }
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Chip8Error> {
+        let truncated = |_| Chip8Error::InvalidMovie {
+            reason: "unexpected end of data",
+        };
+        let mut reader = StateReader::new(data);
+        if reader.read_bytes(MOVIE_MAGIC.len()).map_err(truncated)? != MOVIE_MAGIC {
+            return Err(Chip8Error::InvalidMovie {
+                reason: "not a movie",
+            });
+        }
+        let version = reader.read_u8().map_err(truncated)?;
+        if !(OLDEST_MOVIE_VERSION..=MOVIE_VERSION).contains(&version) {
+            return Err(Chip8Error::InvalidMovie {
+                reason: "unsupported version",
+            });
+        }
+
+        let rom_hash = reader.read_u64().map_err(truncated)?;
+        let seed = reader.read_u64().map_err(truncated)?;
+        let platform = Platform::from_id(reader.read_u8().map_err(truncated)?).ok_or(
+            Chip8Error::InvalidMovie {
+                reason: "unknown platform",
+            },
+        )?;
+        let quirks = Quirks::from_bits(reader.read_u8().map_err(truncated)?);
+        let ipf = reader.read_u16().map_err(truncated)?;
+        if ipf == 0 {
+            return Err(Chip8Error::InvalidMovie {
+                reason: "zero instructions per frame",
+            });
+        }
+        let vip_timing = version >= 2 && reader.read_bool().map_err(truncated)?;
+        let has_hash = reader.read_bool().map_err(truncated)?;
+        let hash = reader.read_u64().map_err(truncated)?;
+        let count = reader.read_u32().map_err(truncated)?;
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            frames.push(reader.read_u16().map_err(truncated)?);
+        }
+        if !reader.is_empty() {
+            return Err(Chip8Error::InvalidMovie {
+                reason: "trailing data",
+            });
+        }
+
+        Ok(Movie {
+            rom_hash,
+            seed,
+            platform,
+            quirks,
+            ipf,
+            vip_timing,
+            frames,
+            final_hash: if has_hash { Some(hash) } else { None },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Movie;
+    use crate::chip8::Chip8;
+    use crate::error::Chip8Error;
+    use crate::headless::{screen_hash, Limit};
+    use crate::quirks::Quirks;
+
+    // F00A C10F D015 1200: wait for a key, then draw at column key, random row
+    const ROM: [u8; 8] = [0xF0, 0x0A, 0xC1, 0x0F, 0xD0, 0x15, 0x12, 0x00];
+
+    #[test]
+    fn test_movie_replays() {
+        let chip8 = Chip8::new(Quirks::default()).with_seed(99);
+        let mut movie = Movie::new(&ROM, &chip8, 4);
+        movie.frames = vec![0, 0, 1 << 7, 1 << 7, 0, 1 << 3, 0];
+
+        let mut first = movie.playback(&ROM).unwrap();
+        first.run(Limit::Frames(movie.len() as u64)).unwrap();
+        movie.final_hash = Some(screen_hash(first.chip8()));
+
+        let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(loaded, movie);
+        let mut second = loaded.playback(&ROM).unwrap();
+        second.run(Limit::Frames(loaded.len() as u64)).unwrap();
+        assert_eq!(Some(screen_hash(second.chip8())), loaded.final_hash);
+        assert_eq!(second.chip8().save_state(), first.chip8().save_state());
+    }
+
+    #[test]
+    fn test_movie_errors() {
+        let movie = Movie::new(&ROM, &Chip8::new(Quirks::default()), 10);
+        assert_eq!(
+            movie.machine(&ROM[..6]).err(),
+            Some(Chip8Error::InvalidMovie {
+                reason: "recorded with a different ROM"
+            })
+        );
+
+        let bytes = movie.to_bytes();
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Chip8Error::InvalidMovie {
+                reason: "unexpected end of data"
+            })
+        );
+        let zero_ipf = Movie::new(&ROM, &Chip8::new(Quirks::default()), 0);
+        assert_eq!(
+            Movie::from_bytes(&zero_ipf.to_bytes()),
+            Err(Chip8Error::InvalidMovie {
+                reason: "zero instructions per frame"
+            })
+        );
+        assert_eq!(
+            Movie::from_bytes(b"C8ST"),
+            Err(Chip8Error::InvalidMovie {
+                reason: "not a movie"
+            })
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::config_dir;
+
+/// An RGB color.
+pub type Rgb = [u8; 3];
+
+/// Colors a palette can hold, one for each value of a four-plane pixel.
+pub const MAX_COLORS: usize = 16;
+
+// Background, plane 1, plane 2 and both planes of each preset
+const PRESETS: [(&str, [Rgb; 4]); 5] = [
+    (
+        "default",
+        [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]],
+    ),
+    (
+        "octo",
+        [
+            [0x99, 0x66, 0x00],
+            [0xFF, 0xCC, 0x00],
+            [0xFF, 0x66, 0x00],
+            [0x66, 0x22, 0x00],
+        ],
+    ),
+    (
+        "green",
+        [
+            [0x00, 0x14, 0x00],
+            [0x33, 0xFF, 0x33],
+            [0x1E, 0x9E, 0x1E],
+            [0x0F, 0x4F, 0x0F],
+        ],
+    ),
+    (
+        "amber",
+        [
+            [0x1A, 0x10, 0x00],
+            [0xFF, 0xB0, 0x00],
+            [0xB3, 0x7B, 0x00],
+            [0x66, 0x46, 0x00],
+        ],
+    ),
+    (
+        "gameboy",
+        [
+            [0x9B, 0xBC, 0x0F],
+            [0x0F, 0x38, 0x0F],
+            [0x30, 0x62, 0x30],
+            [0x8B, 0xAC, 0x0F],
+        ],
+    ),
+];
+
+/// Screen colors by pixel value: the background, then one color for each
+/// combination of lit planes, so plane 1, plane 2 and both for XO-CHIP.
+/// CHIP-8 and SUPER-CHIP only use the first two.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Between 2 and `MAX_COLORS` colors.
+    pub colors: Vec<Rgb>,
+}
+
+impl Default for Palette {
+    /// White on black, with XO-CHIP's second plane in grays.
+    fn default() -> Self {
+        Palette {
+            colors: PRESETS[0].1.to_vec(),
+        }
+    }
+}
+
+impl Palette {
+    /// Names accepted by `preset`.
+    pub const PRESETS: [&'static str; 5] = ["default", "octo", "green", "amber", "gameboy"];
+
+    /// The default grays, Octo's default colors, green or amber phosphor on
+    /// black, or the shades of the original Game Boy.
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, colors)| Palette {
+                colors: colors.to_vec(),
+            })
+    }
+
+    /// Parses comma-separated hex colors like `000000,33ff66`, optionally
+    /// with a leading `#`, in pixel value order.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let colors: Vec<_> = spec.split(',').collect();
+        Palette::from_hex(&colors)
+    }
+
+    /// Builds a palette from hex colors in pixel value order. Colors for
+    /// the first four values that are left out keep their defaults.
+    pub fn from_hex(colors: &[&str]) -> Result<Self, String> {
+        if colors.len() > MAX_COLORS {
+            return Err(format!(
+                "at most {} colors, got {}",
+                MAX_COLORS,
+                colors.len()
+            ));
+        }
+        let mut palette = Palette::default();
+        for (idx, color) in colors.iter().enumerate() {
+            let rgb = parse_color(color.trim())
+                .ok_or_else(|| format!("invalid color {}, expected RRGGBB", color.trim()))?;
+            match palette.colors.get_mut(idx) {
+                Some(slot) => *slot = rgb,
+                None => palette.colors.push(rgb),
+            }
+        }
+        Ok(palette)
+    }
+
+    /// The color of a pixel value. Values past the end of the palette show
+    /// in the plane 1 color, so every lit pixel stays visible.
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors
+            .get(pixel as usize)
+            .copied()
+            .unwrap_or(self.colors[1])
+    }
+}
+
+/// `palettes.toml` in the config directory, see `parse_palettes`.
+pub fn user_palettes_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("palettes.toml"))
+}
+
+/// The palettes defined in the user's palettes file, if there is one.
+pub fn load_user_palettes() -> Result<Vec<(String, Palette)>, String> {
+    let Some(path) = user_palettes_path().filter(|path| path.exists()) else {
+        return Ok(Vec::new());
+    };
+    let name = path.display();
+    let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", name, err))?;
+    parse_palettes(&text).map_err(|err| format!("{}:{}", name, err))
+}
+
+/// Parses a palettes file, one palette per line as a TOML array of hex
+/// colors:
+///
+/// ```toml
+/// # background, plane 1, plane 2, both planes
+/// ice = ["#001020", "#a0e0ff", "#4080a0", "#203040"]
+/// ```
+///
+/// Errors start with the line number.
+pub fn parse_palettes(text: &str) -> Result<Vec<(String, Palette)>, String> {
+    let mut palettes: Vec<(String, Palette)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("{}: {}", index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected NAME = [COLORS]".to_string()))?;
+        let name = name.trim();
+        let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if name.is_empty() || !name.chars().all(bare) {
+            return Err(error(format!("invalid palette name {}", name)));
+        }
+        if palettes.iter().any(|(other, _)| other == name) {
+            return Err(error(format!("{} is defined twice", name)));
+        }
+
+        let (list, rest) = value
+            .trim()
+            .strip_prefix('[')
+            .and_then(|list| list.split_once(']'))
+            .ok_or_else(|| error("expected a list of colors in [ ]".to_string()))?;
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error(format!("unexpected {}", rest)));
+        }
+        let colors = list
+            .split(',')
+            .map(|color| color.trim())
+            .filter(|color| !color.is_empty())
+            .map(|color| {
+                color
+                    .strip_prefix('"')
+                    .and_then(|color| color.strip_suffix('"'))
+                    .ok_or_else(|| format!("{} needs to be quoted", color))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+        let palette = Palette::from_hex(&colors).map_err(error)?;
+        palettes.push((name.to_string(), palette));
+    }
+    Ok(palettes)
+}
+
+fn parse_color(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_palettes, Palette};
+
+    #[test]
+    fn test_parse() {
+        let palette = Palette::parse("#102030, FFaa00").unwrap();
+        assert_eq!(palette.colors[0], [0x10, 0x20, 0x30]);
+        assert_eq!(palette.colors[1], [0xFF, 0xAA, 0x00]);
+        assert_eq!(palette.colors[2..], Palette::default().colors[2..]);
+
+        // Values past the last color fall back to plane 1
+        let sixteen = Palette::parse(&["000000"; 16].join(",")).unwrap();
+        assert_eq!(sixteen.colors.len(), 16);
+        assert_eq!(palette.color(3), [85, 85, 85]);
+        assert_eq!(palette.color(9), [0xFF, 0xAA, 0x00]);
+
+        assert_eq!(
+            Palette::parse("000000,fff").unwrap_err(),
+            "invalid color fff, expected RRGGBB"
+        );
+        assert_eq!(
+            Palette::parse(&["0"; 17].join(",")).unwrap_err(),
+            "at most 16 colors, got 17"
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        for name in Palette::PRESETS {
+            assert_eq!(Palette::preset(name).unwrap().colors.len(), 4);
+        }
+        assert_eq!(Palette::preset("default"), Some(Palette::default()));
+        assert_eq!(
+            Palette::preset("GameBoy").unwrap().colors[0],
+            [0x9B, 0xBC, 0x0F]
+        );
+        assert_eq!(Palette::preset("cga"), None);
+    }
+
+    #[test]
+    fn test_parse_palettes() {
+        let palettes = parse_palettes(
+            "# mine\n\
+             ice = [\"#001020\", \"a0e0ff\"] # cold\n\
+             \n\
+             sixteen = [\"000000\", \"111111\", \"222222\", \"333333\", \"444444\"]\n",
+        )
+        .unwrap();
+        assert_eq!(palettes.len(), 2);
+        assert_eq!(palettes[0].0, "ice");
+        assert_eq!(palettes[0].1.colors[1], [0xA0, 0xE0, 0xFF]);
+        assert_eq!(palettes[1].1.colors.len(), 5);
+
+        let error = |text| parse_palettes(text).unwrap_err();
+        assert_eq!(error("a = [\"000000\"]\na = []"), "2: a is defined twice");
+        assert_eq!(
+            error("a = \"000000\""),
+            "1: expected a list of colors in [ ]"
+        );
+        assert_eq!(error("a = [000000]"), "1: 000000 needs to be quoted");
+        assert_eq!(error("a b = [\"000000\"]"), "1: invalid palette name a b");
+    }
+}
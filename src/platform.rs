@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use crate::quirks::Quirks;
+
+/// The interpreter family a ROM was written for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// COSMAC VIP CHIP-8. SUPER-CHIP opcodes are decoded on every platform,
+    /// so the two only differ in their default quirks.
+    #[default]
+    Chip8,
+    SuperChip,
+    /// XO-CHIP: 64K memory, two bitplanes, audio patterns.
+    XoChip,
+}
+
+impl Platform {
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 4096,
+            Platform::XoChip => 65536,
+        }
+    }
+
+    /// Guesses the platform from Octo's file extensions: `.xo8` for XO-CHIP,
+    /// `.sc8` for SUPER-CHIP and CHIP-8 for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("xo8") => Platform::XoChip,
+            Some("sc8") => Platform::SuperChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    /// Looks up a platform by its command line name: `chip8`, `schip` or
+    /// `xochip`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::vip(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    /// Instructions per 60Hz frame that suit most ROMs for the platform.
+    pub fn default_ipf(&self) -> usize {
+        match self {
+            Platform::Chip8 => 10,
+            Platform::SuperChip => 30,
+            Platform::XoChip => 100,
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Platform::Chip8),
+            1 => Some(Platform::SuperChip),
+            2 => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
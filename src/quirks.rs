@@ -0,0 +1,97 @@
+/// Behaviour switches for the opcodes that were interpreted differently by
+/// the COSMAC VIP, CHIP-48/SUPER-CHIP and XO-CHIP interpreters.
+///
+/// The default has every switch off, which is how this emulator behaved
+/// before quirks became configurable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// BNNN jumps to VX + NNN (X being the high nibble of NNN) instead of V0 + NNN.
+    pub jump_uses_vx: bool,
+    /// FX55/FX65 leave I pointing past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60Hz interrupt before drawing.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            jump_uses_vx: false,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 and SUPER-CHIP 1.1 on the HP48.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            jump_uses_vx: true,
+            load_store_increments_i: false,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            jump_uses_vx: false,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    /// Looks up a set of quirks by its command line name: `vip`, `schip`,
+    /// `xochip`, or `none` for the default.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Quirks::default()),
+            "vip" => Some(Quirks::vip()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+
+    /// Packs the switches into one byte, in field order from bit 0.
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.jump_uses_vx,
+            self.load_store_increments_i,
+            self.logic_resets_vf,
+            self.clip_sprites,
+            self.display_wait,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (idx, &on)| bits | ((on as u8) << idx))
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let bit = |idx: u8| bits & (1 << idx) != 0;
+        Quirks {
+            shift_uses_vy: bit(0),
+            jump_uses_vx: bit(1),
+            load_store_increments_i: bit(2),
+            logic_resets_vf: bit(3),
+            clip_sprites: bit(4),
+            display_wait: bit(5),
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use crate::error::Chip8Error;
+use crate::state::{StateReader, StateWriter};
+
 const NUM_REGS: usize = 16;
 
 pub struct IRegister {
@@ -20,6 +23,15 @@ impl IRegister {
     pub fn reset(&mut self) {
         self.write(0);
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.value);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.value = reader.read_u16()?;
+        Ok(())
+    }
 }
 
 impl Default for IRegister {
@@ -34,28 +46,44 @@ pub struct VRegister {
 
 impl VRegister {
     pub fn new() -> Self {
-        VRegister { regs: [0; NUM_REGS] }
+        VRegister {
+            regs: [0; NUM_REGS],
+        }
     }
 
-    pub fn read(&self, idx: usize) -> u8 {
+    pub fn read(&self, idx: usize) -> Result<u8, Chip8Error> {
         if idx >= NUM_REGS {
-            panic!("Trying to access register bigger than 0xF");
+            return Err(Chip8Error::InvalidRegister { idx });
         }
 
-        self.regs[idx]
+        Ok(self.regs[idx])
     }
 
-    pub fn write(&mut self, idx: usize, value: u8) {
+    pub fn write(&mut self, idx: usize, value: u8) -> Result<(), Chip8Error> {
         if idx >= NUM_REGS {
-            panic!("Trying to access register bigger than 0xF");
+            return Err(Chip8Error::InvalidRegister { idx });
         }
 
         self.regs[idx] = value;
+        Ok(())
+    }
+
+    pub fn values(&self) -> [u8; NUM_REGS] {
+        self.regs
     }
 
     pub fn reset(&mut self) {
         self.regs = [0; NUM_REGS];
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.regs);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        self.regs.copy_from_slice(reader.read_bytes(NUM_REGS)?);
+        Ok(())
+    }
 }
 
 impl Default for VRegister {
@@ -0,0 +1,269 @@
+use crate::filter::FilterChain;
+use crate::palette::{Palette, Rgb};
+
+/// An RGB image ready to be shown, one color per pixel, row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+/// How pixels that were just erased stay on screen. CHIP-8 games move
+/// sprites by XORing them off and on again, so without help anything
+/// redrawn every frame flickers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Persistence {
+    /// Every frame as the program left it.
+    Off,
+    /// A pixel is lit if it was lit in this frame or the one before.
+    Or,
+    /// Like a phosphor screen: lit pixels light up at once and fade back to
+    /// the background by this many percent of their brightness per frame.
+    Fade(u8),
+}
+
+impl Persistence {
+    /// Fade rate when `fade` is given without one.
+    pub const DEFAULT_FADE: u8 = 35;
+
+    /// Parses `off`, `or`, `fade` or `fade:PERCENT`, the percentage being
+    /// between 1 and 100.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (mode, rate) = match text.split_once(':') {
+            Some((mode, rate)) => (mode, Some(rate)),
+            None => (text, None),
+        };
+        match (mode, rate) {
+            ("off", None) => Ok(Persistence::Off),
+            ("or", None) => Ok(Persistence::Or),
+            ("fade", None) => Ok(Persistence::Fade(Persistence::DEFAULT_FADE)),
+            ("fade", Some(rate)) => match rate.parse() {
+                Ok(rate @ 1..=100) => Ok(Persistence::Fade(rate)),
+                _ => Err(format!(
+                    "fade rate {} is not a percentage from 1 to 100",
+                    rate
+                )),
+            },
+            _ => Err(format!("unknown persistence mode {}", text)),
+        }
+    }
+}
+
+/// How the screen fills a window of a different shape or size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scaling {
+    /// As large as fits with the screen's aspect ratio kept.
+    #[default]
+    Fit,
+    /// The largest whole multiple of the screen that fits, so every
+    /// emulated pixel is the same size.
+    Integer,
+}
+
+impl Scaling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fit" => Some(Scaling::Fit),
+            "integer" => Some(Scaling::Integer),
+            _ => None,
+        }
+    }
+}
+
+/// Where a `width` x `height` screen goes in a window of the given size,
+/// as x, y, width and height, centered with black bars around it.
+pub fn viewport(
+    (width, height): (u32, u32),
+    (window_width, window_height): (u32, u32),
+    scaling: Scaling,
+) -> (i32, i32, u32, u32) {
+    let (out_width, out_height) = match scaling {
+        Scaling::Fit => {
+            // Whichever side is the tighter fit decides the size
+            if window_width as u64 * height as u64 <= window_height as u64 * width as u64 {
+                (window_width, window_width * height / width)
+            } else {
+                (window_height * width / height, window_height)
+            }
+        }
+        Scaling::Integer => {
+            let scale = (window_width / width).min(window_height / height).max(1);
+            (width * scale, height * scale)
+        }
+    };
+    let x = (window_width as i32 - out_width as i32) / 2;
+    let y = (window_height as i32 - out_height as i32) / 2;
+    (x, y, out_width, out_height)
+}
+
+/// Turns the pixel values of the emulated screen into colors, keeping
+/// what persistence needs from earlier frames, and runs the filters.
+pub struct Renderer {
+    palette: Palette,
+    persistence: Persistence,
+    filters: FilterChain,
+    // Pixel values of the last frame, for `Or`
+    previous: Vec<u8>,
+    // Colors last shown, before rounding, for `Fade`
+    glow: Vec<[f32; 3]>,
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, persistence: Persistence) -> Self {
+        Renderer {
+            palette,
+            persistence,
+            filters: FilterChain::default(),
+            previous: Vec::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+    }
+
+    /// Switches persistence mode, starting over from the next frame.
+    pub fn set_persistence(&mut self, persistence: Persistence) {
+        self.persistence = persistence;
+        self.previous.clear();
+        self.glow.clear();
+    }
+
+    /// Colors a `width` x `height` screen of pixel values. A change of
+    /// resolution drops whatever persisted from the old one. Filters may
+    /// return a larger frame.
+    pub fn render(&mut self, screen: &[u8], width: usize, height: usize) -> Frame {
+        let pixels = match self.persistence {
+            Persistence::Off => screen
+                .iter()
+                .map(|&pixel| self.palette.color(pixel))
+                .collect(),
+            Persistence::Or => {
+                let pixels = if self.previous.len() == screen.len() {
+                    screen
+                        .iter()
+                        .zip(&self.previous)
+                        .map(|(&pixel, &previous)| self.palette.color(pixel | previous))
+                        .collect()
+                } else {
+                    screen
+                        .iter()
+                        .map(|&pixel| self.palette.color(pixel))
+                        .collect()
+                };
+                self.previous = screen.to_vec();
+                pixels
+            }
+            Persistence::Fade(rate) => self.fade(screen, rate),
+        };
+        self.filters.apply(Frame {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn fade(&mut self, screen: &[u8], rate: u8) -> Vec<Rgb> {
+        let background = self.palette.color(0).map(f32::from);
+        if self.glow.len() != screen.len() {
+            self.glow = vec![background; screen.len()];
+        }
+
+        let keep = 1.0 - f32::from(rate) / 100.0;
+        let mut pixels = Vec::with_capacity(screen.len());
+        for (&pixel, glow) in screen.iter().zip(self.glow.iter_mut()) {
+            if pixel != 0 {
+                *glow = self.palette.color(pixel).map(f32::from);
+            } else {
+                for (channel, &target) in glow.iter_mut().zip(&background) {
+                    *channel = target + (*channel - target) * keep;
+                }
+            }
+            pixels.push(glow.map(|channel| channel.round() as u8));
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{viewport, Persistence, Renderer, Scaling};
+    use crate::palette::Palette;
+
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const BLACK: [u8; 3] = [0, 0, 0];
+
+    #[test]
+    fn test_or() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::Or);
+        assert_eq!(renderer.render(&[1, 0], 2, 1).pixels, [WHITE, BLACK]);
+        // The sprite moved right: both positions stay lit for a frame
+        assert_eq!(renderer.render(&[0, 1], 2, 1).pixels, [WHITE, WHITE]);
+        assert_eq!(renderer.render(&[0, 1], 2, 1).pixels, [BLACK, WHITE]);
+        // Planes combine like the pixels they come from
+        assert_eq!(renderer.render(&[2, 2], 2, 1).pixels[1], [85, 85, 85]);
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut renderer = Renderer::new(Palette::default(), Persistence::Fade(50));
+        assert_eq!(renderer.render(&[1], 1, 1).pixels, [WHITE]);
+        assert_eq!(renderer.render(&[0], 1, 1).pixels, [[128; 3]]);
+        assert_eq!(renderer.render(&[0], 1, 1).pixels, [[64; 3]]);
+        assert_eq!(renderer.render(&[1], 1, 1).pixels, [WHITE]);
+        for _ in 0..10 {
+            renderer.render(&[0], 1, 1);
+        }
+        assert_eq!(renderer.render(&[0], 1, 1).pixels, [BLACK]);
+
+        // A new resolution starts dark
+        assert_eq!(renderer.render(&[0, 0], 2, 1).pixels, [BLACK, BLACK]);
+    }
+
+    #[test]
+    fn test_viewport() {
+        // Wider than 2:1 leaves bars left and right
+        assert_eq!(
+            viewport((64, 32), (1000, 320), Scaling::Fit),
+            (180, 0, 640, 320)
+        );
+        assert_eq!(
+            viewport((128, 64), (960, 640), Scaling::Fit),
+            (0, 80, 960, 480)
+        );
+        assert_eq!(
+            viewport((64, 32), (1000, 700), Scaling::Integer),
+            (20, 110, 960, 480)
+        );
+        // Too small for even one window pixel per screen pixel
+        assert_eq!(
+            viewport((128, 64), (100, 50), Scaling::Integer),
+            (-14, -7, 128, 64)
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Persistence::parse("off"), Ok(Persistence::Off));
+        assert_eq!(Persistence::parse("or"), Ok(Persistence::Or));
+        assert_eq!(
+            Persistence::parse("fade"),
+            Ok(Persistence::Fade(Persistence::DEFAULT_FADE))
+        );
+        assert_eq!(Persistence::parse("fade:20"), Ok(Persistence::Fade(20)));
+        assert_eq!(
+            Persistence::parse("fade:0").unwrap_err(),
+            "fade rate 0 is not a percentage from 1 to 100"
+        );
+        assert_eq!(
+            Persistence::parse("blur").unwrap_err(),
+            "unknown persistence mode blur"
+        );
+    }
+}
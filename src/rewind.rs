@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+
+const FRAMES_PER_SECOND: usize = 60;
+
+// First byte of every delta
+const DELTA_XOR: u8 = 0;
+const DELTA_FULL: u8 = 1;
+
+/// Ring buffer of per-frame save states for running time backwards.
+///
+/// Only the newest state is kept whole. Every older frame is stored as a
+/// delta that turns the state after it back into itself: the XOR of both
+/// states, run-length encoded so that untouched memory costs next to nothing.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Keeps up to `seconds` of history at 60 frames per second.
+    pub fn new(seconds: usize) -> Self {
+        Self::with_frames(seconds * FRAMES_PER_SECOND)
+    }
+
+    pub fn with_frames(capacity: usize) -> Self {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Records the current state as the newest frame, dropping the oldest one
+    /// once the history is full.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &latest));
+            while self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Steps `chip8` back by one recorded frame. Returns `false` once the
+    /// oldest frame has been reached.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let (latest, delta) = match (self.latest.as_ref(), self.deltas.pop_back()) {
+            (Some(latest), Some(delta)) => (latest, delta),
+            _ => return Ok(false),
+        };
+        let previous = apply_delta(latest, &delta);
+        chip8.load_state(&previous)?;
+        self.latest = Some(previous);
+        Ok(true)
+    }
+
+    /// Number of frames that can still be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Approximate heap usage of the recorded history in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        latest + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+/// Encodes `target` relative to `base` as alternating runs of
+/// (unchanged length, changed length, changed XOR bytes).
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    if base.len() != target.len() {
+        let mut delta = vec![DELTA_FULL];
+        delta.extend_from_slice(target);
+        return delta;
+    }
+
+    let mut delta = vec![DELTA_XOR];
+    let mut pos = 0;
+    while pos < base.len() {
+        let same = base[pos..]
+            .iter()
+            .zip(&target[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        pos += same;
+        let changed = base[pos..]
+            .iter()
+            .zip(&target[pos..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        write_varint(&mut delta, same);
+        write_varint(&mut delta, changed);
+        for idx in pos..pos + changed {
+            delta.push(base[idx] ^ target[idx]);
+        }
+        pos += changed;
+    }
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == DELTA_FULL {
+        return delta[1..].to_vec();
+    }
+
+    let mut target = base.to_vec();
+    let mut pos = 0;
+    let mut cursor = 1;
+    while cursor < delta.len() {
+        pos += read_varint(delta, &mut cursor);
+        let changed = read_varint(delta, &mut cursor);
+        for byte in &mut target[pos..pos + changed] {
+            *byte ^= delta[cursor];
+            cursor += 1;
+        }
+        pos += changed;
+    }
+    target
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, encode_delta, Rewind};
+    use crate::chip8::Chip8;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base: Vec<u8> = (0..=255).collect();
+        let mut target = base.clone();
+        target[3] = 0;
+        target[200..210].copy_from_slice(&[7; 10]);
+
+        let delta = encode_delta(&base, &target);
+        assert!(delta.len() < 32);
+        assert_eq!(apply_delta(&base, &delta), target);
+        assert_eq!(
+            apply_delta(&target, &encode_delta(&target, &target)),
+            target
+        );
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &[1, 2])), [1, 2]);
+    }
+
+    #[test]
+    fn test_rewind_frames() {
+        // 7001 1200: count up in V0 forever
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        let mut rewind = Rewind::with_frames(3);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            chip8.tick().unwrap();
+            chip8.tick().unwrap();
+            rewind.record(&chip8);
+            states.push(chip8.save_state());
+        }
+        assert_eq!(rewind.len(), 3);
+
+        for expected in states[1..4].iter().rev() {
+            assert!(rewind.rewind(&mut chip8).unwrap());
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewind.rewind(&mut chip8).unwrap());
+
+        // Recording resumes from the rewound frame
+        rewind.record(&chip8);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_rewind_across_platform_change() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        let mut rewind = Rewind::new(1);
+        rewind.record(&chip8);
+        let before = chip8.save_state();
+
+        chip8 = Chip8::new(Quirks::xochip()).with_platform(Platform::XoChip);
+        rewind.record(&chip8);
+        assert!(rewind.rewind(&mut chip8).unwrap());
+        assert_eq!(chip8.save_state(), before);
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+use crate::hash::sha1_hex;
+use crate::json::Json;
+use crate::keymap::Keymap;
+use crate::movie::MAX_IPF;
+use crate::palette::Palette;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+// Entries for the ROMs in roms/, in the chip-8-database format
+const BUNDLED: &str = include_str!("../data/programs.json");
+
+// Input names of the database and the controller buttons they become
+const DATABASE_BUTTONS: [(&str, &str); 6] = [
+    ("up", "dpup"),
+    ("down", "dpdown"),
+    ("left", "dpleft"),
+    ("right", "dpright"),
+    ("a", "a"),
+    ("b", "b"),
+];
+
+/// Settings the database has for one ROM. Anything it leaves out is `None`
+/// and falls back to the usual defaults.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    /// Instructions per frame, the database's `tickrate`.
+    pub ipf: Option<usize>,
+    /// Controller buttons for the game's directions and actions.
+    pub keymap: Option<Keymap>,
+    /// The colors the game was designed for.
+    pub palette: Option<Palette>,
+}
+
+/// ROM settings keyed by SHA-1, read from the `programs.json` of the
+/// community CHIP-8 database (github.com/chip-8/chip-8-database).
+///
+/// Each program lists its ROMs by hash. From a ROM entry the first
+/// `platforms` entry this emulator supports picks the platform and default
+/// quirks, `quirkyPlatforms` adjusts the quirks, `tickrate` sets the IPF,
+/// `keys` binds the controller and the `pixels` of `colors` make up the
+/// palette.
+#[derive(Clone, Debug, Default)]
+pub struct RomDb {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDb {
+    /// The entries built into the emulator.
+    pub fn bundled() -> Self {
+        RomDb::parse(BUNDLED).expect("bundled database is valid")
+    }
+
+    /// The bundled entries, overridden by the user's database file if there
+    /// is one, see `user_path`.
+    pub fn load() -> Result<Self, String> {
+        let mut db = RomDb::bundled();
+        if let Some(path) = RomDb::user_path().filter(|path| path.exists()) {
+            let name = path.display();
+            let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", name, err))?;
+            db.merge(RomDb::parse(&text).map_err(|err| format!("{}:{}", name, err))?);
+        }
+        Ok(db)
+    }
+
+    /// `$XDG_CONFIG_HOME/chip8/programs.json`, or the same under
+    /// `~/.config`.
+    pub fn user_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("programs.json"))
+    }
+
+    /// Reads a database in the `programs.json` format. Syntax errors start
+    /// with the line number.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json = Json::parse(text).map_err(|err| err.to_string())?;
+        let programs = json.as_array().ok_or(" expected a list of programs")?;
+
+        let mut roms = HashMap::new();
+        for program in programs {
+            let title = program
+                .get("title")
+                .and_then(Json::as_str)
+                .ok_or(" program without a title")?;
+            let entries = program
+                .get("roms")
+                .and_then(Json::as_object)
+                .ok_or_else(|| format!(" {}: roms must be an object", title))?;
+            for (hash, rom) in entries {
+                let info = parse_rom(title, rom).map_err(|err| format!(" {}: {}", title, err))?;
+                roms.insert(hash.to_ascii_lowercase(), info);
+            }
+        }
+        Ok(RomDb { roms })
+    }
+
+    /// Adds the entries of `other`, replacing those for the same ROMs.
+    pub fn merge(&mut self, other: RomDb) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&sha1_hex(rom))
+    }
+}
+
+fn parse_rom(title: &str, rom: &Json) -> Result<RomInfo, String> {
+    // Platforms this emulator can't run, like CHIP-8X, are skipped
+    let platforms = match rom.get("platforms") {
+        Some(platforms) => platforms.as_array().ok_or("platforms must be a list")?,
+        None => &[],
+    };
+    let supported = platforms
+        .iter()
+        .filter_map(Json::as_str)
+        .find_map(|id| database_platform(id).map(|(platform, quirks)| (id, platform, quirks)));
+
+    let quirks = supported.map(|(id, _, quirks)| {
+        match rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id)) {
+            Some(overrides) => apply_quirks(quirks, overrides),
+            None => quirks,
+        }
+    });
+
+    let ipf = match rom.get("tickrate") {
+        Some(tickrate) => Some(
+            tickrate
+                .as_u64()
+                .filter(|tickrate| (1..=MAX_IPF as u64).contains(tickrate))
+                .ok_or_else(|| format!("tickrate must be between 1 and {}", MAX_IPF))?
+                as usize,
+        ),
+        None => None,
+    };
+
+    let keymap = match rom.get("keys") {
+        Some(keys) => {
+            let keys = keys.as_object().ok_or("keys must be an object")?;
+            let mut buttons = Vec::new();
+            for (input, key) in keys {
+                let key = key
+                    .as_u64()
+                    .filter(|&key| key < 16)
+                    .ok_or_else(|| format!("invalid key for {}", input))?;
+                // Second player inputs and the like have no button
+                if let Some((_, button)) = DATABASE_BUTTONS.iter().find(|(name, _)| name == input) {
+                    buttons.push((*button, key as usize));
+                }
+            }
+            Some(Keymap::from_buttons(buttons))
+        }
+        None => None,
+    };
+
+    let palette = match rom.get("colors").and_then(|colors| colors.get("pixels")) {
+        Some(pixels) => {
+            let colors = pixels
+                .as_array()
+                .and_then(|pixels| pixels.iter().map(Json::as_str).collect::<Option<Vec<_>>>())
+                .ok_or("pixels must be a list of colors")?;
+            Some(Palette::from_hex(&colors)?)
+        }
+        None => None,
+    };
+
+    Ok(RomInfo {
+        title: title.to_string(),
+        platform: supported.map(|(_, platform, _)| platform),
+        quirks,
+        ipf,
+        keymap,
+        palette,
+    })
+}
+
+/// Platform and quirks for one of the database's platform ids.
+fn database_platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::vip())),
+        // All of the database's quirk flags off
+        "modernChip8" => Some((
+            Platform::Chip8,
+            Quirks {
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                load_store_increments_i: true,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+        )),
+        "chip48" | "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::schip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
+
+/// Applies the database's quirk flags, each of which names a deviation
+/// from the original VIP. `memoryIncrementByX` is not supported.
+fn apply_quirks(mut quirks: Quirks, overrides: &Json) -> Quirks {
+    let flag = |name| overrides.get(name).and_then(Json::as_bool);
+    if let Some(shift) = flag("shift") {
+        quirks.shift_uses_vy = !shift;
+    }
+    if let Some(unchanged) = flag("memoryLeaveIUnchanged") {
+        quirks.load_store_increments_i = !unchanged;
+    }
+    if let Some(wrap) = flag("wrap") {
+        quirks.clip_sprites = !wrap;
+    }
+    if let Some(jump) = flag("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(vblank) = flag("vblank") {
+        quirks.display_wait = vblank;
+    }
+    if let Some(logic) = flag("logic") {
+        quirks.logic_resets_vf = logic;
+    }
+    quirks
+}
+
+/// Platform and quirks for the ROM at `path`. The platform is `platform`
+/// if given, else the database's, else guessed from the file extension. The
+/// database's quirks go with its platform; any other platform gets the
+/// quirks this emulator has always used for it.
+pub fn platform_and_quirks(
+    info: Option<&RomInfo>,
+    platform: Option<Platform>,
+    path: &Path,
+) -> (Platform, Quirks) {
+    let known = info.and_then(|info| Some((info.platform?, info.quirks?)));
+    let platform = platform
+        .or(known.map(|(platform, _)| platform))
+        .unwrap_or_else(|| Platform::from_path(path));
+    match known {
+        Some((known, quirks)) if known == platform => (platform, quirks),
+        _ if platform == Platform::Chip8 => (platform, Quirks::default()),
+        _ => (platform, platform.default_quirks()),
+    }
+}
+
+/// Platform, quirks and instructions per frame for the ROM at `path`, the
+/// same for every frontend. `platform` and `ipf` come from the command
+/// line; whatever it leaves out is taken from the database, then from the
+/// platform, see `platform_and_quirks`.
+pub fn rom_settings(
+    info: Option<&RomInfo>,
+    platform: Option<Platform>,
+    ipf: Option<usize>,
+    path: &Path,
+) -> (Platform, Quirks, usize) {
+    let (platform, quirks) = platform_and_quirks(info, platform, path);
+    let ipf = ipf
+        .or(info.and_then(|info| info.ipf))
+        .unwrap_or_else(|| platform.default_ipf());
+    (platform, quirks, ipf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{platform_and_quirks, rom_settings, RomDb};
+    use crate::keymap::{KeyKind, Keymap};
+    use crate::palette::Palette;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use std::path::Path;
+
+    const TETRIS: &[u8] = include_bytes!("../roms/TETRIS.ch8");
+
+    #[test]
+    fn test_bundled() {
+        let db = RomDb::bundled();
+        let info = db.lookup(TETRIS).unwrap();
+        assert_eq!(info.title, "Tetris");
+        assert_eq!(info.platform, Some(Platform::Chip8));
+        assert_eq!(info.quirks, Some(Quirks::default()));
+        assert_eq!(info.ipf, None);
+        let keymap = info.keymap.as_ref().unwrap();
+        assert_eq!(keymap.bindings.len(), 4);
+        assert!(keymap
+            .bindings
+            .iter()
+            .all(|binding| binding.kind == KeyKind::Button));
+        assert!(db.lookup(&TETRIS[1..]).is_none());
+
+        // Extensions still decide for unknown ROMs
+        assert_eq!(
+            platform_and_quirks(None, None, Path::new("game.sc8")),
+            (Platform::SuperChip, Quirks::schip())
+        );
+    }
+
+    #[test]
+    fn test_user_override() {
+        let mut db = RomDb::bundled();
+        db.merge(
+            RomDb::parse(
+                r##"[{
+                    "title": "Tetris (fast)",
+                    "roms": {
+                        "5F518084744BF3CB8733F6E5454DFD1634320563": {
+                            "platforms": ["megachip8", "originalChip8"],
+                            "quirkyPlatforms": {
+                                "originalChip8": { "vblank": false, "shift": true }
+                            },
+                            "tickrate": 30,
+                            "keys": { "a": 4, "player2Up": 1 },
+                            "colors": { "pixels": ["#000000", "#ff8000"], "buzzer": "#ffffff" }
+                        }
+                    }
+                }]"##,
+            )
+            .unwrap(),
+        );
+
+        let info = db.lookup(TETRIS).unwrap();
+        assert_eq!(info.title, "Tetris (fast)");
+        assert_eq!(info.ipf, Some(30));
+        let path = Path::new("TETRIS.ch8");
+        assert_eq!(rom_settings(Some(info), None, None, path).2, 30);
+        assert_eq!(rom_settings(Some(info), None, Some(7), path).2, 7);
+        let quirks = Quirks {
+            display_wait: false,
+            shift_uses_vy: false,
+            ..Quirks::vip()
+        };
+        assert_eq!(
+            platform_and_quirks(Some(info), None, Path::new("TETRIS.ch8")),
+            (Platform::Chip8, quirks)
+        );
+        // A platform from the command line brings its own quirks
+        assert_eq!(
+            platform_and_quirks(Some(info), Some(Platform::XoChip), Path::new("TETRIS.ch8")),
+            (Platform::XoChip, Quirks::xochip())
+        );
+        assert_eq!(info.keymap, Some(Keymap::from_buttons([("a", 4)])));
+        assert_eq!(info.palette, Some(Palette::parse("000000,ff8000").unwrap()));
+
+        assert_eq!(
+            RomDb::parse("[{\"title\": \"x\", \"roms\": []}]").unwrap_err(),
+            " x: roms must be an object"
+        );
+        assert_eq!(RomDb::parse("[\n{]").unwrap_err(), "2: expected a key");
+
+        // modernChip8 has no quirks in database terms, which are VIP behaviour
+        // for shifts, FX55/FX65 and sprites at the edges
+        let modern =
+            RomDb::parse(r#"[{"title": "m", "roms": {"00": {"platforms": ["modernChip8"]}}}]"#)
+                .unwrap();
+        let quirks = modern.roms["00"].quirks.unwrap();
+        assert!(quirks.shift_uses_vy);
+        assert!(quirks.load_store_increments_i);
+        assert!(quirks.clip_sprites);
+
+        for tickrate in ["0", "65536"] {
+            let text = format!(
+                "[{{\"title\": \"x\", \"roms\": {{\"00\": {{\"tickrate\": {}}}}}}}]",
+                tickrate
+            );
+            assert_eq!(
+                RomDb::parse(&text).unwrap_err(),
+                " x: tickrate must be between 1 and 65535"
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+use crate::timing::VIP_MAX_IPF;
+
+/// Emulated frames per second; the timers count down once per frame.
+pub const FRAME_RATE: u64 = 60;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// Past this many frames behind, e.g. after a stall, the backlog is dropped
+// instead of fast-forwarding through it
+const MAX_FRAMES_BEHIND: u64 = 6;
+
+/// Paces emulation at exactly 60 frames per second of host time, running a
+/// fixed number of instructions per frame (IPF) independent of how often the
+/// host loop comes around.
+///
+/// The frontend reports elapsed time through `advance` and runs as many
+/// frames as it returns. Time is kept in whole nanoseconds scaled by the
+/// frame rate, so frames never drift against the wall clock.
+pub struct Scheduler {
+    ipf: usize,
+    // Elapsed nanoseconds times FRAME_RATE not yet turned into frames
+    pending: u64,
+}
+
+impl Scheduler {
+    pub fn new(ipf: usize) -> Self {
+        Scheduler { ipf, pending: 0 }
+    }
+
+    /// Paces `chip8` at `ipf` instructions per frame, unless it has VIP
+    /// timing: then every frame runs until its share of the 1.76MHz clock
+    /// is spent, whatever the instruction count.
+    pub fn for_chip8(chip8: &Chip8, ipf: usize) -> Self {
+        if chip8.vip_timing() {
+            Scheduler::new(VIP_MAX_IPF)
+        } else {
+            Scheduler::new(ipf)
+        }
+    }
+
+    /// Instructions to run per frame.
+    pub fn ipf(&self) -> usize {
+        self.ipf
+    }
+
+    pub fn set_ipf(&mut self, ipf: usize) {
+        self.ipf = ipf;
+    }
+
+    /// Accounts for `elapsed` host time and returns the number of frames that
+    /// are now due.
+    pub fn advance(&mut self, elapsed: Duration) -> u64 {
+        let scaled = (elapsed.as_nanos() as u64).saturating_mul(FRAME_RATE);
+        self.pending = self.pending.saturating_add(scaled);
+        let frames = self.pending / NANOS_PER_SEC;
+        self.pending %= NANOS_PER_SEC;
+        frames.min(MAX_FRAMES_BEHIND)
+    }
+
+    /// Host time left until the next frame is due.
+    pub fn until_next_frame(&self) -> Duration {
+        let remaining = NANOS_PER_SEC - self.pending;
+        Duration::from_nanos(remaining.div_ceil(FRAME_RATE))
+    }
+
+    /// Forgets partially elapsed time, e.g. after sitting in the debugger.
+    pub fn reset(&mut self) {
+        self.pending = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+    use crate::timing::VIP_MAX_IPF;
+    use std::time::Duration;
+
+    #[test]
+    fn test_frames_follow_host_time() {
+        let mut scheduler = Scheduler::new(10);
+        // A 144Hz host loop runs 60 frames in one second, no drift
+        let frames: u64 = (0..144)
+            .map(|_| scheduler.advance(Duration::from_nanos(1_000_000_000 / 144 + 1)))
+            .sum();
+        assert_eq!(frames, 60);
+
+        // A 30Hz host loop runs two frames per iteration
+        scheduler.reset();
+        assert_eq!(scheduler.advance(Duration::from_millis(34)), 2);
+        assert_eq!(scheduler.ipf(), 10);
+
+        let vip = Chip8::new(Quirks::vip()).with_vip_timing(true);
+        assert_eq!(Scheduler::for_chip8(&vip, 10).ipf(), VIP_MAX_IPF);
+    }
+
+    #[test]
+    fn test_stall_drops_backlog() {
+        let mut scheduler = Scheduler::new(10);
+        assert_eq!(scheduler.advance(Duration::from_secs(5)), 6);
+        assert_eq!(scheduler.advance(Duration::ZERO), 0);
+        assert_eq!(
+            scheduler.until_next_frame(),
+            Duration::from_nanos(16_666_667)
+        );
+
+        scheduler.advance(Duration::from_millis(10));
+        assert!(scheduler.until_next_frame() < Duration::from_millis(7));
+    }
+}
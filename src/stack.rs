@@ -1,3 +1,6 @@
+use crate::error::Chip8Error;
+use crate::state::{StateReader, StateWriter};
+
 const STACK_SIZE: usize = 16;
 
 pub struct Stack {
@@ -13,22 +16,65 @@ impl Stack {
         };
         stack
     }
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
+
         self.stack[self.sp as usize] = value;
         self.sp += 1;
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
+
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(self.stack[self.sp as usize])
     }
 
     pub fn get(&self, idx: usize) -> u16 {
         self.stack[idx]
     }
 
+    pub fn depth(&self) -> usize {
+        self.sp as usize
+    }
+
+    pub fn entries(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
     pub fn reset(&mut self) {
         self.sp = 0;
         self.stack = [0; STACK_SIZE];
     }
-}
\ No newline at end of file
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for value in self.stack {
+            writer.write_u16(value);
+        }
+        writer.write_u8(self.sp);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Chip8Error> {
+        for value in self.stack.iter_mut() {
+            *value = reader.read_u16()?;
+        }
+        self.sp = reader.read_u8()?;
+        if self.sp as usize > STACK_SIZE {
+            return Err(Chip8Error::InvalidState {
+                reason: "stack pointer out of range",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
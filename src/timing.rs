@@ -0,0 +1,73 @@
+/// Clock rate of the COSMAC VIP's CDP1802 in Hz.
+pub const VIP_CLOCK_HZ: u32 = 1_760_640;
+
+/// Clock pulses per 1802 machine cycle.
+pub const CLOCKS_PER_MACHINE_CYCLE: u32 = 8;
+
+/// Machine cycles between two 60Hz display interrupts.
+pub const VIP_CYCLES_PER_FRAME: u32 = VIP_CLOCK_HZ / CLOCKS_PER_MACHINE_CYCLE / 60;
+
+// 6XNN is the cheapest instruction
+const MIN_CYCLES: u32 = 6;
+
+/// Enough instructions per frame to always use up a VIP frame's cycles, for
+/// frontends that run a fixed count per frame.
+pub const VIP_MAX_IPF: usize = (VIP_CYCLES_PER_FRAME / MIN_CYCLES + 1) as usize;
+
+/// Machine cycles the VIP interpreter spends on `op`, including fetch and
+/// decode. The figures are rounded from published measurements of the
+/// original interpreter; where the cost depends on the data, like the digits
+/// of FX33, a typical case is used.
+///
+/// DXYN only counts the drawing itself; waiting for the interrupt is handled
+/// by the caller. Opcodes the VIP never had are charged like a register load.
+pub fn vip_cycles(op: u16) -> u32 {
+    let x = ((op & 0x0F00) >> 8) as u32;
+    let n = (op & 0x000F) as u32;
+    match op >> 12 {
+        0x0 => match op {
+            0x00E0 => 24,
+            0x00EE => 10,
+            // Machine code subroutine, its cost is unknown
+            _ => 10,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10,
+        0x5 | 0x9 => 14,
+        0x6 => MIN_CYCLES,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        0xD => 26 + 17 * n,
+        0xE => 14,
+        _ => match op & 0xFF {
+            0x07 | 0x0A | 0x15 | 0x18 => 10,
+            0x1E => 18,
+            0x29 => 20,
+            0x33 => 204,
+            0x55 | 0x65 => 14 + 8 * (x + 1),
+            _ => 10,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{vip_cycles, VIP_CYCLES_PER_FRAME, VIP_MAX_IPF};
+
+    #[test]
+    fn test_vip_cycles() {
+        assert_eq!(VIP_CYCLES_PER_FRAME, 3668);
+        // Every opcode costs at least as much as 6XNN
+        let cheapest = (0..=0xFFFF).map(vip_cycles).min().unwrap();
+        assert_eq!(cheapest, vip_cycles(0x6000));
+        assert!(VIP_MAX_IPF as u32 * cheapest > VIP_CYCLES_PER_FRAME);
+
+        // Sprites and register dumps get slower with size
+        assert!(vip_cycles(0xD00F) > vip_cycles(0xD001));
+        assert!(vip_cycles(0xFF55) > vip_cycles(0xF055));
+    }
+}
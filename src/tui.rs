@@ -0,0 +1,295 @@
+use std::io::{self, Write};
+
+use crate::chip8::Chip8;
+use crate::debugger::print_registers;
+use crate::drivers::{Beeper, Display, KeyEdge, Keypad};
+use crate::keymap::{KeyKind, Keymap};
+
+/// Frames a key stays down after the terminal last sent it. Terminals
+/// report no releases, only presses and the keyboard's auto-repeat, so
+/// this needs to outlast the pause before repeating starts.
+pub const HOLD_FRAMES: u8 = 30;
+
+/// Characters the screen is drawn with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Glyphs {
+    /// Half blocks, one character for 1x2 pixels.
+    #[default]
+    HalfBlocks,
+    /// Braille dots, one character for 2x4 pixels, for hi-res screens in
+    /// small terminals.
+    Braille,
+}
+
+impl Glyphs {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "half" => Some(Glyphs::HalfBlocks),
+            "braille" => Some(Glyphs::Braille),
+            _ => None,
+        }
+    }
+
+    /// One string per line of text for a `width` x `height` screen, with
+    /// every nonzero pixel lit.
+    pub fn render(&self, screen: &[u8], width: usize, height: usize) -> Vec<String> {
+        let lit = |x: usize, y: usize| x < width && y < height && screen[y * width + x] != 0;
+        match self {
+            Glyphs::HalfBlocks => (0..height)
+                .step_by(2)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| match (lit(x, y), lit(x, y + 1)) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        })
+                        .collect()
+                })
+                .collect(),
+            Glyphs::Braille => (0..height)
+                .step_by(4)
+                .map(|y| {
+                    (0..width)
+                        .step_by(2)
+                        .map(|x| {
+                            // Dots 1-3 and 4-6 run down the two columns,
+                            // dots 7 and 8 are the bottom row
+                            let dots = [
+                                (0, 0),
+                                (0, 1),
+                                (0, 2),
+                                (1, 0),
+                                (1, 1),
+                                (1, 2),
+                                (0, 3),
+                                (1, 3),
+                            ];
+                            let bits = dots
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, &(dx, dy))| lit(x + dx, y + dy))
+                                .fold(0, |bits, (bit, _)| bits | 1 << bit);
+                            char::from_u32(0x2800 + bits).unwrap()
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The screen with the registers of `chip8` next to it, as one string
+/// that redraws the terminal from the top left corner.
+pub fn compose(screen: &[String], chip8: &Chip8) -> String {
+    let mut registers = Vec::new();
+    print_registers(chip8, &mut registers).unwrap();
+    let registers = String::from_utf8(registers).unwrap();
+    let panel: Vec<&str> = registers.lines().collect();
+
+    // Home the cursor, clear the rest of each line as it is written and
+    // whatever a taller screen left below
+    let mut text = String::from("\x1b[H");
+    for idx in 0..screen.len().max(panel.len()) {
+        let line = screen.get(idx).map_or("", String::as_str);
+        let padding = screen
+            .first()
+            .map_or(0, |first| first.chars().count())
+            .saturating_sub(line.chars().count());
+        text.push_str(&format!(
+            "{}{} │ {}\x1b[K\r\n",
+            line,
+            " ".repeat(padding),
+            panel.get(idx).unwrap_or(&"")
+        ));
+    }
+    text.push_str("\x1b[J");
+    text
+}
+
+/// Keeps the text of the last screen drawn.
+#[derive(Default)]
+pub struct TerminalDisplay {
+    glyphs: Glyphs,
+    lines: Vec<String>,
+}
+
+impl TerminalDisplay {
+    pub fn new(glyphs: Glyphs) -> Self {
+        TerminalDisplay {
+            glyphs,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+impl Display for TerminalDisplay {
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize) {
+        self.lines = self.glyphs.render(screen, width, height);
+    }
+}
+
+/// Rings the terminal bell when the sound timer starts, or stays silent.
+pub struct TerminalBeeper {
+    bell: bool,
+    playing: bool,
+}
+
+impl TerminalBeeper {
+    pub fn new(bell: bool) -> Self {
+        TerminalBeeper {
+            bell,
+            playing: false,
+        }
+    }
+}
+
+impl Beeper for TerminalBeeper {
+    fn set_playing(&mut self, playing: bool) {
+        if playing && !self.playing && self.bell {
+            let mut stdout = io::stdout();
+            // A bell that can't be rung is no reason to stop the game
+            let _ = stdout.write_all(b"\x07").and_then(|()| stdout.flush());
+        }
+        self.playing = playing;
+    }
+}
+
+/// Keypad keys held by characters typed into the terminal. Each one stays
+/// down for `HOLD_FRAMES` after it was last typed.
+pub struct TerminalKeypad {
+    // Characters of the keymap's keycode bindings, lowercase
+    chars: Vec<(char, usize)>,
+    // Frames left for each keypad key, zero when up
+    held: [u8; 16],
+    edges: Vec<KeyEdge>,
+}
+
+impl TerminalKeypad {
+    /// Takes the keys from the single-character keycode bindings of
+    /// `keymap`, such as a layout preset.
+    pub fn new(keymap: &Keymap) -> Self {
+        let chars = keymap
+            .bindings
+            .iter()
+            .filter(|binding| binding.kind == KeyKind::Keycode)
+            .filter_map(|binding| {
+                let mut chars = binding.name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some((c.to_ascii_lowercase(), binding.key)),
+                    _ => None,
+                }
+            })
+            .collect();
+        TerminalKeypad {
+            chars,
+            held: [0; 16],
+            edges: Vec::new(),
+        }
+    }
+
+    /// Handles a typed character, ignoring those outside the keypad.
+    pub fn typed(&mut self, c: char) {
+        let c = c.to_ascii_lowercase();
+        if let Some(&(_, key)) = self.chars.iter().find(|(bound, _)| *bound == c) {
+            if self.held[key] == 0 {
+                self.edges.push(KeyEdge { key, pressed: true });
+            }
+            self.held[key] = HOLD_FRAMES;
+        }
+    }
+
+    /// Counts one frame off every held key, releasing those that run out.
+    pub fn frame(&mut self) {
+        for (key, frames) in self.held.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                if *frames == 0 {
+                    self.edges.push(KeyEdge {
+                        key,
+                        pressed: false,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Keypad for TerminalKeypad {
+    fn is_pressed(&self, key: usize) -> bool {
+        self.held[key] > 0
+    }
+
+    fn take_edges(&mut self) -> Vec<KeyEdge> {
+        std::mem::take(&mut self.edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compose, Glyphs, TerminalKeypad, HOLD_FRAMES};
+    use crate::chip8::Chip8;
+    use crate::drivers::{KeyEdge, Keypad};
+    use crate::keymap::Keymap;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_render() {
+        #[rustfmt::skip]
+        let screen = [
+            1, 0, 1, 1,
+            0, 0, 1, 0,
+            0, 1, 0, 0,
+            2, 0, 0, 0,
+        ];
+        assert_eq!(Glyphs::HalfBlocks.render(&screen, 4, 4), ["▀ █▀", "▄▀  "]);
+        // Dots 1, 6 and 7 on the left, 1, 2 and 4 on the right
+        assert_eq!(Glyphs::Braille.render(&screen, 4, 4), ["⡡⠋"]);
+        // Odd sizes leave the missing pixels dark
+        assert_eq!(Glyphs::HalfBlocks.render(&[1, 1], 2, 1), ["▀▀"]);
+    }
+
+    #[test]
+    fn test_compose() {
+        let chip8 = Chip8::new(Quirks::default());
+        let text = compose(&["██".to_string(), " ▀".to_string()], &chip8);
+        let lines: Vec<_> = text.split("\r\n").collect();
+        assert!(lines[0].starts_with("\x1b[H██ │ V0=00"));
+        assert!(lines[1].starts_with(" ▀ │ V8=00"));
+        // The panel goes on below the screen
+        assert!(lines[2].starts_with("   │ PC=200"));
+    }
+
+    #[test]
+    fn test_keypad() {
+        let mut keypad = TerminalKeypad::new(&Keymap::preset("qwerty").unwrap());
+        keypad.typed('W');
+        keypad.typed('w');
+        keypad.typed('?');
+        assert!(keypad.is_pressed(0x5));
+        for _ in 0..HOLD_FRAMES - 1 {
+            keypad.frame();
+        }
+        assert!(keypad.is_pressed(0x5));
+        keypad.frame();
+        assert!(!keypad.is_pressed(0x5));
+        assert_eq!(
+            keypad.take_edges(),
+            [
+                KeyEdge {
+                    key: 0x5,
+                    pressed: true
+                },
+                KeyEdge {
+                    key: 0x5,
+                    pressed: false
+                }
+            ]
+        );
+    }
+}